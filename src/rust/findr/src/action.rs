@@ -0,0 +1,430 @@
+use crate::{expr::Candidate, predicate, EntryType, MyResult};
+use chrono::{DateTime, Local};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, Metadata},
+    io::{self, Write},
+    path::Path,
+    process::Command,
+};
+
+/// The most bytes of paths given to one `-exec ... +` command, well under
+/// the usual limit on the size of an argument list.
+const BATCH_BYTES: usize = 128 * 1024;
+
+/// The strftime letters `%T` accepts, besides `@` and `+`.
+const TIME_LETTERS: &str = "aAbBcdDFhHIjklmMprsStTuUVwWxXyYZ";
+
+/// What an expression does with the entries it reaches.
+#[derive(Debug)]
+pub enum Action {
+    /// `-print`: the path and a newline. It's added to an expression that
+    /// has no action of its own.
+    Print,
+    /// `-print0`: the path and a NUL, for `xargs -0`.
+    Print0,
+    /// `-printf FORMAT`.
+    Printf(Vec<Directive>),
+    /// `-delete`: removes files and empty directories. Directories are
+    /// visited after their contents when an expression deletes. A failure
+    /// is remembered for the exit status.
+    Delete { failed: bool },
+    /// `-exec COMMAND ;` or `-exec COMMAND {} +`.
+    Exec(Exec),
+}
+
+impl Action {
+    /// Runs the action, returning whether it succeeded. Only failing to
+    /// write the output is an error; other failures are reported and make
+    /// the action false, as in find.
+    pub fn run(&mut self, candidate: &Candidate, out: &mut dyn Write) -> MyResult<bool> {
+        let path = candidate.entry().path();
+
+        match self {
+            Action::Print => {
+                write_path(out, path.as_os_str())?;
+                out.write_all(b"\n")?
+            }
+            Action::Print0 => {
+                write_path(out, path.as_os_str())?;
+                out.write_all(b"\0")?
+            }
+            Action::Printf(format) => {
+                for directive in format.iter() {
+                    directive.write(candidate, out)?;
+                }
+            }
+            Action::Delete { failed } => {
+                let deleted = delete(candidate);
+                *failed |= !deleted;
+                return Ok(deleted);
+            }
+            Action::Exec(exec) => return exec.run(path, out),
+        }
+
+        Ok(true)
+    }
+
+    /// Runs whatever is left of a batched `-exec` once the walk is done,
+    /// returning whether every batch it ran and every deletion succeeded.
+    pub fn finish(&mut self, out: &mut dyn Write) -> MyResult<bool> {
+        match self {
+            Action::Exec(exec) => {
+                exec.run_batch(out)?;
+                Ok(!exec.failed)
+            }
+            Action::Delete { failed } => Ok(!*failed),
+            _ => Ok(true),
+        }
+    }
+}
+
+fn delete(candidate: &Candidate) -> bool {
+    let entry = candidate.entry();
+    // Like find, don't try to remove the directory being searched from.
+    if entry.depth() == 0 && entry.path() == Path::new(".") {
+        return true;
+    }
+
+    let result = if entry.file_type().is_dir() {
+        fs::remove_dir(entry.path())
+    } else {
+        fs::remove_file(entry.path())
+    };
+    result
+        .map_err(|e| eprintln!("{}: {}", entry.path().display(), e))
+        .is_ok()
+}
+
+/// A command run by `-exec`, where `{}` stands for the path. With `+`, the
+/// paths are collected and the command is run on as many at once as fit.
+#[derive(Debug)]
+pub struct Exec {
+    command: Vec<String>,
+    batch: Option<Vec<OsString>>,
+    batch_bytes: usize,
+    /// Whether a batch failed. Its paths already counted as true, so like
+    /// find, this only shows in the exit status.
+    failed: bool,
+}
+
+impl Exec {
+    /// `command` leaves out the `{}` that ends a batched command.
+    pub fn new(command: Vec<String>, batched: bool) -> Self {
+        Exec {
+            command,
+            batch: batched.then(Vec::new),
+            batch_bytes: 0,
+            failed: false,
+        }
+    }
+
+    fn run(&mut self, path: &Path, out: &mut dyn Write) -> MyResult<bool> {
+        let Some(batch) = &mut self.batch else {
+            let args = self
+                .command
+                .iter()
+                .map(|arg| replace_braces(arg, path.as_os_str()))
+                .collect();
+            return spawn(args, out);
+        };
+
+        self.batch_bytes += path.as_os_str().len() + 1;
+        batch.push(path.as_os_str().to_os_string());
+        if self.batch_bytes >= BATCH_BYTES {
+            self.run_batch(out)?;
+        }
+        Ok(true)
+    }
+
+    fn run_batch(&mut self, out: &mut dyn Write) -> MyResult<()> {
+        let Some(batch) = self.batch.as_mut().filter(|batch| !batch.is_empty()) else {
+            return Ok(());
+        };
+
+        let args = self
+            .command
+            .iter()
+            .map(OsString::from)
+            .chain(batch.drain(..))
+            .collect();
+        self.batch_bytes = 0;
+        if !spawn(args, out)? {
+            self.failed = true;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces each `{}` in `arg` with `path`.
+fn replace_braces(arg: &str, path: &OsStr) -> OsString {
+    let mut parts = arg.split("{}");
+    let mut replaced = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        replaced.push(path);
+        replaced.push(part);
+    }
+    replaced
+}
+
+/// Runs a command once what's already been printed is out, returning
+/// whether it exited successfully.
+fn spawn(args: Vec<OsString>, out: &mut dyn Write) -> MyResult<bool> {
+    out.flush()?;
+    match Command::new(&args[0]).args(&args[1..]).status() {
+        Ok(status) => Ok(status.success()),
+        Err(e) => {
+            eprintln!("{}: {}", args[0].to_string_lossy(), e);
+            Ok(false)
+        }
+    }
+}
+
+/// One piece of a `-printf` format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    Text(String),
+    /// `%p`: the path.
+    Path,
+    /// `%f`: the file name.
+    Name,
+    /// `%h`: the directories leading to the file name.
+    Dir,
+    /// `%P`: the path below the starting point.
+    Relative,
+    /// `%H`: the starting point.
+    Root,
+    /// `%s`: the size in bytes.
+    Size,
+    /// `%d`: the depth below the starting point.
+    Depth,
+    /// `%m`: the permission bits in octal.
+    Mode,
+    /// `%M`: the type and permissions as `ls -l` shows them.
+    Perms,
+    /// `%y`: the type as a `-type` letter.
+    Type,
+    /// `%Y`: the type of what a symlink points to, or `N` when it's broken.
+    TargetType,
+    /// `%t`, or `%T` with `@`, `+` or a strftime letter: the modification
+    /// time, with `%t` stored as `c`.
+    Time(char),
+}
+
+impl Directive {
+    /// Parses a `-printf` format with find's `%` directives and `\`
+    /// escapes.
+    pub fn parse_format(format: &str) -> MyResult<Vec<Directive>> {
+        let mut directives = vec![];
+        let mut text = String::new();
+        let mut chars = format.chars();
+
+        while let Some(c) = chars.next() {
+            let directive = match c {
+                '\\' => {
+                    text.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        _ => return Err("unknown escape".into()),
+                    });
+                    continue;
+                }
+                '%' => match chars.next() {
+                    Some('%') => {
+                        text.push('%');
+                        continue;
+                    }
+                    Some('p') => Directive::Path,
+                    Some('f') => Directive::Name,
+                    Some('h') => Directive::Dir,
+                    Some('P') => Directive::Relative,
+                    Some('H') => Directive::Root,
+                    Some('s') => Directive::Size,
+                    Some('d') => Directive::Depth,
+                    Some('m') => Directive::Mode,
+                    Some('M') => Directive::Perms,
+                    Some('y') => Directive::Type,
+                    Some('Y') => Directive::TargetType,
+                    Some('t') => Directive::Time('c'),
+                    Some('T') => match chars.next() {
+                        Some(k) if k == '@' || k == '+' || TIME_LETTERS.contains(k) => {
+                            Directive::Time(k)
+                        }
+                        _ => return Err("unknown time directive".into()),
+                    },
+                    _ => return Err("unknown directive".into()),
+                },
+                c => {
+                    text.push(c);
+                    continue;
+                }
+            };
+
+            if !text.is_empty() {
+                directives.push(Directive::Text(std::mem::take(&mut text)));
+            }
+            directives.push(directive);
+        }
+        if !text.is_empty() {
+            directives.push(Directive::Text(text));
+        }
+
+        Ok(directives)
+    }
+
+    fn write(&self, candidate: &Candidate, out: &mut dyn Write) -> MyResult<()> {
+        let entry = candidate.entry();
+        let path = entry.path();
+        // Like find, anything that can't be read prints as nothing.
+        let metadata = || candidate.metadata();
+
+        match self {
+            Directive::Text(text) => write!(out, "{}", text)?,
+            Directive::Path => write_path(out, path.as_os_str())?,
+            Directive::Name => write_path(out, entry.file_name())?,
+            Directive::Dir => match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                Some(dir) => write_path(out, dir.as_os_str())?,
+                None => write!(out, ".")?,
+            },
+            Directive::Relative => {
+                if let Ok(relative) = path.strip_prefix(candidate.root()) {
+                    write_path(out, relative.as_os_str())?
+                }
+            }
+            Directive::Root => write_path(out, candidate.root().as_os_str())?,
+            Directive::Size => {
+                if let Some(metadata) = metadata() {
+                    write!(out, "{}", metadata.len())?
+                }
+            }
+            Directive::Depth => write!(out, "{}", entry.depth())?,
+            Directive::Mode => {
+                if let Some(mode) = metadata().and_then(predicate::mode) {
+                    write!(out, "{:o}", mode)?
+                }
+            }
+            Directive::Perms => {
+                if let Some(metadata) = metadata() {
+                    write!(out, "{}", symbolic_mode(metadata))?
+                }
+            }
+            Directive::Type => write!(out, "{}", type_letter(metadata()))?,
+            Directive::TargetType => write!(out, "{}", target_type_letter(path))?,
+            Directive::Time(letter) => {
+                if let Some(modified) = metadata().and_then(|m| m.modified().ok()) {
+                    write!(out, "{}", format_time(DateTime::from(modified), *letter))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a path as it is, so names that aren't UTF-8 come out unchanged
+/// where the platform allows.
+#[cfg(unix)]
+fn write_path(out: &mut dyn Write, path: &OsStr) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    out.write_all(path.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_path(out: &mut dyn Write, path: &OsStr) -> io::Result<()> {
+    out.write_all(path.to_string_lossy().as_bytes())
+}
+
+/// Formats a time the way find's `%t` and `%T` do, with ten digits of
+/// fractional seconds where find shows them.
+fn format_time(time: DateTime<Local>, letter: char) -> String {
+    let fraction = format!("{:09}0", time.timestamp_subsec_nanos());
+    match letter {
+        '@' => format!("{}.{}", time.timestamp(), fraction),
+        '+' => format!("{}.{}", time.format("%Y-%m-%d+%H:%M:%S"), fraction),
+        'c' => format!(
+            "{}.{} {}",
+            time.format("%a %b %e %H:%M:%S"),
+            fraction,
+            time.format("%Y")
+        ),
+        'S' | 'T' => format!("{}.{}", time.format(&format!("%{}", letter)), fraction),
+        _ => time.format(&format!("%{}", letter)).to_string(),
+    }
+}
+
+fn type_letter(metadata: Option<&Metadata>) -> char {
+    metadata
+        .and_then(|metadata| EntryType::of(metadata.file_type()))
+        .map_or('U', EntryType::letter)
+}
+
+fn target_type_letter(path: &Path) -> char {
+    match fs::metadata(path) {
+        Ok(metadata) => type_letter(Some(&metadata)),
+        Err(_) if path.is_symlink() => 'N',
+        Err(_) => 'U',
+    }
+}
+
+/// Renders the type and permissions like `-rw-r--r--`.
+fn symbolic_mode(metadata: &Metadata) -> String {
+    let type_char = match type_letter(Some(metadata)) {
+        'f' => '-',
+        letter => letter,
+    };
+    let Some(mode) = predicate::mode(metadata) else {
+        return type_char.to_string();
+    };
+
+    let mut perms = String::from(type_char);
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        perms.push(if bits & 4 != 0 { 'r' } else { '-' });
+        perms.push(if bits & 2 != 0 { 'w' } else { '-' });
+        perms.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    perms
+}
+
+#[cfg(test)]
+mod test {
+    use super::{replace_braces, Directive};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            Directive::parse_format("%p %s\\n").unwrap(),
+            vec![
+                Directive::Path,
+                Directive::Text(" ".to_string()),
+                Directive::Size,
+                Directive::Text("\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            Directive::parse_format("100%% %T@").unwrap(),
+            vec![Directive::Text("100% ".to_string()), Directive::Time('@')]
+        );
+        assert!(Directive::parse_format("%q").is_err());
+        assert!(Directive::parse_format("%Tq").is_err());
+        assert!(Directive::parse_format("%").is_err());
+    }
+
+    #[test]
+    fn test_replace_braces() {
+        let path = OsStr::new("a/b");
+        assert_eq!(replace_braces("{}", path), "a/b");
+        assert_eq!(replace_braces("--file={}.bak", path), "--file=a/b.bak");
+        assert_eq!(replace_braces("none", path), "none");
+    }
+}
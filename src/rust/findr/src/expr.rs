@@ -0,0 +1,537 @@
+use crate::{
+    action::{Action, Directive, Exec},
+    predicate::{self, AgeTest, PermTest, SizeTest},
+    walk::Entry,
+    EntryType, MyResult,
+};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use std::{
+    cell::OnceCell,
+    fs::{self, Metadata},
+    io::Write,
+    path::Path,
+    time::SystemTime,
+};
+
+/// A predicate tree over directory entries, with actions at its leaves
+/// alongside the tests.
+#[derive(Debug)]
+pub enum Expr {
+    True,
+    Test(Test),
+    Action(Action),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+pub enum Test {
+    /// `-name`/`-iname`: a glob matched against the file name.
+    Name(GlobMatcher),
+    /// `-path`/`-wholename`: a glob matched against the whole path.
+    Path(GlobMatcher),
+    /// `-regex`/`-iregex`: a regex matched against the whole path.
+    Regex(Regex),
+    Type(EntryType),
+    /// `-xtype`: the type of what a symlink points to, or of the link itself
+    /// when links are followed.
+    XType(EntryType),
+    Size(SizeTest),
+    Mtime(AgeTest),
+    Newer(SystemTime),
+    Perm(PermTest),
+    User(u32),
+    Group(u32),
+    Empty,
+}
+
+/// An entry being tested, whose metadata is read at most once and only if
+/// a test needs it.
+pub struct Candidate<'a> {
+    entry: &'a Entry,
+    root: &'a Path,
+    metadata: OnceCell<Option<Metadata>>,
+    now: SystemTime,
+}
+
+impl<'a> Candidate<'a> {
+    /// `root` is the starting point `entry` was found under.
+    pub fn new(entry: &'a Entry, root: &'a Path, now: SystemTime) -> Self {
+        Candidate {
+            entry,
+            root,
+            metadata: OnceCell::new(),
+            now,
+        }
+    }
+
+    pub fn entry(&self) -> &'a Entry {
+        self.entry
+    }
+
+    pub fn root(&self) -> &'a Path {
+        self.root
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| self.entry.metadata().ok())
+            .as_ref()
+    }
+}
+
+impl Expr {
+    /// Joins two expressions with `-and`, leaving out a `True` operand.
+    pub fn and(self, other: Expr) -> Expr {
+        match (self, other) {
+            (Expr::True, expr) | (expr, Expr::True) => expr,
+            (left, right) => Expr::And(Box::new(left), Box::new(right)),
+        }
+    }
+
+    /// Joins expressions with `-or`; an empty list matches everything.
+    pub fn any(exprs: impl IntoIterator<Item = Expr>) -> Expr {
+        exprs
+            .into_iter()
+            .reduce(|left, right| Expr::Or(Box::new(left), Box::new(right)))
+            .unwrap_or(Expr::True)
+    }
+
+    /// Evaluates the expression for `candidate` left to right, running the
+    /// actions it reaches and skipping the right side of `-and` and `-or`
+    /// once the left side decides the result.
+    pub fn eval(&mut self, candidate: &Candidate, out: &mut dyn Write) -> MyResult<bool> {
+        Ok(match self {
+            Expr::True => true,
+            Expr::Test(test) => test.matches(candidate),
+            Expr::Action(action) => action.run(candidate, out)?,
+            Expr::Not(expr) => !expr.eval(candidate, out)?,
+            Expr::And(left, right) => left.eval(candidate, out)? && right.eval(candidate, out)?,
+            Expr::Or(left, right) => left.eval(candidate, out)? || right.eval(candidate, out)?,
+        })
+    }
+
+    /// Runs what's left of any batched actions once the walk is done,
+    /// returning whether all of their batches and deletions succeeded.
+    pub fn finish(&mut self, out: &mut dyn Write) -> MyResult<bool> {
+        match self {
+            Expr::Action(action) => action.finish(out),
+            Expr::Not(expr) => expr.finish(out),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let left = left.finish(out)?;
+                Ok(right.finish(out)? && left)
+            }
+            Expr::True | Expr::Test(_) => Ok(true),
+        }
+    }
+
+    /// Returns whether the expression has an action for which `f` is true.
+    pub fn any_action(&self, f: &dyn Fn(&Action) -> bool) -> bool {
+        match self {
+            Expr::Action(action) => f(action),
+            Expr::Not(expr) => expr.any_action(f),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.any_action(f) || right.any_action(f)
+            }
+            Expr::True | Expr::Test(_) => false,
+        }
+    }
+}
+
+impl Test {
+    fn matches(&self, candidate: &Candidate) -> bool {
+        let entry = candidate.entry;
+        let metadata = || candidate.metadata();
+
+        match self {
+            Test::Name(glob) => glob.is_match(entry.file_name()),
+            Test::Path(glob) => glob.is_match(entry.path()),
+            Test::Regex(re) => re.is_match(&entry.path().to_string_lossy()),
+            Test::Type(entry_type) => EntryType::of(entry.file_type()) == Some(*entry_type),
+            Test::XType(entry_type) => EntryType::of(entry.other_type()) == Some(*entry_type),
+            Test::Size(test) => metadata().is_some_and(|m| test.matches(m)),
+            Test::Mtime(test) => metadata().is_some_and(|m| test.matches(m, candidate.now)),
+            Test::Newer(time) => metadata()
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|modified| modified > *time),
+            Test::Perm(test) => metadata().is_some_and(|m| test.matches(m)),
+            Test::User(uid) => metadata()
+                .and_then(predicate::owner)
+                .is_some_and(|(owner, _)| owner == *uid),
+            Test::Group(gid) => metadata()
+                .and_then(predicate::owner)
+                .is_some_and(|(_, group)| group == *gid),
+            Test::Empty => metadata().is_some_and(|m| predicate::is_empty(entry.path(), m)),
+        }
+    }
+}
+
+/// Compiles a shell glob that, like find's, must match all of its subject:
+/// `*` and `?` match any characters including `/` and a leading `.`.
+pub fn glob(pattern: &str, case_insensitive: bool) -> MyResult<GlobMatcher> {
+    Ok(GlobBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .backslash_escape(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Compiles a regex that must match the whole path, as find's `-regex` does.
+pub fn regex(pattern: &str, case_insensitive: bool) -> MyResult<Regex> {
+    Ok(RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .case_insensitive(case_insensitive)
+        .build()?)
+}
+
+/// Returns whether `arg` starts an expression, so that it and everything
+/// after it are parsed by [`parse`] rather than as options and paths.
+pub fn is_expression_start(arg: &str) -> bool {
+    matches!(
+        arg,
+        "(" | "!"
+            | "-not"
+            | "-name"
+            | "-iname"
+            | "-path"
+            | "-wholename"
+            | "-regex"
+            | "-iregex"
+            | "-type"
+            | "-xtype"
+            | "-size"
+            | "-mtime"
+            | "-newer"
+            | "-perm"
+            | "-user"
+            | "-group"
+            | "-empty"
+            | "-print"
+            | "-print0"
+            | "-printf"
+            | "-delete"
+            | "-exec"
+            | "-xdev"
+    )
+}
+
+/// Options that find takes among the terms of an expression, such as
+/// `-xdev`. They apply to the whole walk wherever they appear.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub xdev: bool,
+}
+
+/// Parses a find-style expression. `!`/`-not` binds tightest, then
+/// `-a`/`-and` (which may be left out between two terms), then `-o`/`-or`;
+/// parentheses group. Actions such as `-print` are terms too, and options
+/// such as `-xdev` are terms that are always true.
+pub fn parse(args: &[String]) -> MyResult<(Expr, Options)> {
+    if args.is_empty() {
+        return Ok((Expr::True, Options::default()));
+    }
+
+    let mut parser = Parser {
+        args,
+        pos: 0,
+        options: Options::default(),
+    };
+    let result = parser.or().and_then(|expr| match parser.peek() {
+        None => Ok(expr),
+        Some(")") => Err(parser.error("unexpected \")\" without a matching \"(\"")),
+        Some(token) => Err(parser.error(format!("unexpected \"{}\"", token))),
+    });
+
+    match result {
+        Ok(expr) => Ok((expr, parser.options)),
+        Err(e) => Err(e.render(args).into()),
+    }
+}
+
+struct ParseError {
+    message: String,
+    pos: usize,
+}
+
+impl ParseError {
+    /// Formats the message followed by the expression with the offending
+    /// token underlined.
+    fn render(&self, args: &[String]) -> String {
+        let mut line = String::new();
+        let mut marker = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i == self.pos {
+                marker = format!("{}{}", " ".repeat(line.len()), "^".repeat(arg.len().max(1)));
+            }
+            line.push_str(arg);
+            line.push(' ');
+        }
+        if marker.is_empty() {
+            marker = format!("{}^", " ".repeat(line.len()));
+        }
+
+        format!(
+            "Invalid expression: {}\n    {}\n    {}",
+            self.message,
+            line.trim_end(),
+            marker
+        )
+    }
+}
+
+struct Parser<'a> {
+    args: &'a [String],
+    pos: usize,
+    options: Options,
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.args.get(self.pos).map(String::as_str);
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            pos: self.pos,
+        }
+    }
+
+    fn or(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.and()?;
+        while let Some("-o" | "-or") = self.peek() {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.not()?;
+        loop {
+            match self.peek() {
+                None | Some("-o" | "-or" | ")") => return Ok(expr),
+                Some("-a" | "-and") => self.pos += 1,
+                Some(_) => {}
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some("!" | "-not") => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let start = self.pos;
+        let Some(token) = self.next() else {
+            return Err(ParseError {
+                message: "expected an expression".to_string(),
+                pos: start,
+            });
+        };
+
+        let test = match token {
+            "(" => {
+                let expr = self.or()?;
+                return match self.next() {
+                    Some(")") => Ok(expr),
+                    _ => Err(ParseError {
+                        message: "expected \")\"".to_string(),
+                        pos: self.pos - 1,
+                    }),
+                };
+            }
+            "-print" => return Ok(Expr::Action(Action::Print)),
+            "-print0" => return Ok(Expr::Action(Action::Print0)),
+            "-printf" => {
+                let format = self.value(token, Directive::parse_format)?;
+                return Ok(Expr::Action(Action::Printf(format)));
+            }
+            "-delete" => return Ok(Expr::Action(Action::Delete { failed: false })),
+            "-xdev" => {
+                self.options.xdev = true;
+                return Ok(Expr::True);
+            }
+            "-exec" => return self.exec(start).map(Expr::Action),
+            "-empty" => Test::Empty,
+            "-name" => self.value(token, |val| Ok(Test::Name(glob(val, false)?)))?,
+            "-iname" => self.value(token, |val| Ok(Test::Name(glob(val, true)?)))?,
+            "-path" | "-wholename" => self.value(token, |val| Ok(Test::Path(glob(val, false)?)))?,
+            "-regex" => self.value(token, |val| Ok(Test::Regex(regex(val, false)?)))?,
+            "-iregex" => self.value(token, |val| Ok(Test::Regex(regex(val, true)?)))?,
+            "-type" => self.value(token, |val| {
+                EntryType::from_letter(val)
+                    .map(Test::Type)
+                    .ok_or_else(|| "unknown type".into())
+            })?,
+            "-xtype" => self.value(token, |val| {
+                EntryType::from_letter(val)
+                    .map(Test::XType)
+                    .ok_or_else(|| "unknown type".into())
+            })?,
+            "-size" => self.value(token, |val| Ok(Test::Size(SizeTest::parse(val)?)))?,
+            "-mtime" => self.value(token, |val| Ok(Test::Mtime(AgeTest::parse(val)?)))?,
+            "-newer" => self.value(token, |val| Ok(Test::Newer(fs::metadata(val)?.modified()?)))?,
+            "-perm" => self.value(token, |val| Ok(Test::Perm(PermTest::parse(val)?)))?,
+            "-user" => self.value(token, |val| Ok(Test::User(predicate::parse_user(val)?)))?,
+            "-group" => self.value(token, |val| Ok(Test::Group(predicate::parse_group(val)?)))?,
+            ")" | "-o" | "-or" | "-a" | "-and" => {
+                return Err(ParseError {
+                    message: format!("expected an expression before \"{}\"", token),
+                    pos: start,
+                })
+            }
+            _ => {
+                return Err(ParseError {
+                    message: format!("unknown predicate \"{}\"", token),
+                    pos: start,
+                })
+            }
+        };
+
+        Ok(Expr::Test(test))
+    }
+
+    /// Parses the argument of the predicate `name` with `parse`.
+    fn value<T>(&mut self, name: &str, parse: impl FnOnce(&str) -> MyResult<T>) -> ParseResult<T> {
+        let Some(val) = self.next() else {
+            return Err(ParseError {
+                message: format!("missing argument to {}", name),
+                pos: self.pos - 1,
+            });
+        };
+
+        parse(val).map_err(|_| ParseError {
+            message: format!("invalid argument \"{}\" to {}", val, name),
+            pos: self.pos - 1,
+        })
+    }
+
+    /// Parses the command after `-exec`, which ends at `;`, or at `+` right
+    /// after `{}` to run it on many paths at once.
+    fn exec(&mut self, start: usize) -> ParseResult<Action> {
+        let mut command: Vec<String> = vec![];
+        let batched = loop {
+            match self.next() {
+                Some(";") => break false,
+                Some("+") if command.last().is_some_and(|arg| arg == "{}") => {
+                    command.pop();
+                    break true;
+                }
+                Some(arg) => command.push(arg.to_string()),
+                None => {
+                    return Err(ParseError {
+                        message: "missing \";\" or \"{} +\" after -exec".to_string(),
+                        pos: start,
+                    })
+                }
+            }
+        };
+
+        if command.is_empty() {
+            return Err(ParseError {
+                message: "missing command to -exec".to_string(),
+                pos: start,
+            });
+        }
+        Ok(Action::Exec(Exec::new(command, batched)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Expr, Test};
+
+    fn parse_str(expr: &str) -> Result<Expr, String> {
+        let args: Vec<String> = expr.split_whitespace().map(String::from).collect();
+        parse(&args)
+            .map(|(expr, _)| expr)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Renders a tree in prefix form, with names standing for their tests.
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::True => "true".to_string(),
+            Expr::Test(Test::Name(glob)) => glob.glob().glob().to_string(),
+            Expr::Test(Test::Empty) => "empty".to_string(),
+            Expr::Test(test) => format!("{:?}", test),
+            Expr::Action(action) => format!("{:?}", action),
+            Expr::Not(expr) => format!("(not {})", show(expr)),
+            Expr::And(left, right) => format!("(and {} {})", show(left), show(right)),
+            Expr::Or(left, right) => format!("(or {} {})", show(left), show(right)),
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let tree = |expr| show(&parse_str(expr).unwrap());
+        assert_eq!(tree("-name a -o -name b -name c"), "(or a (and b c))");
+        assert_eq!(tree("-name a -a -name b -o -name c"), "(or (and a b) c)");
+        assert_eq!(tree("! -name a -name b"), "(and (not a) b)");
+        assert_eq!(tree("-not ( -name a -or -name b )"), "(not (or a b))");
+        assert_eq!(tree("( -name a ) -empty"), "(and a empty)");
+        assert_eq!(tree("! ! -empty"), "(not (not empty))");
+    }
+
+    #[test]
+    fn test_options() {
+        let args: Vec<String> = ["-name", "a", "-xdev"].map(String::from).to_vec();
+        let (expr, options) = parse(&args).unwrap();
+        assert_eq!(show(&expr), "(and a true)");
+        assert!(options.xdev);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |expr| parse_str(expr).unwrap_err();
+        assert_eq!(
+            error("-name a -foo"),
+            "Invalid expression: unknown predicate \"-foo\"\n    -name a -foo\n            ^^^^"
+        );
+        assert_eq!(
+            error("( -name a"),
+            "Invalid expression: expected \")\"\n    ( -name a\n              ^"
+        );
+        assert_eq!(
+            error("-name a )"),
+            "Invalid expression: unexpected \")\" without a matching \"(\"\n    -name a )\n            ^"
+        );
+        assert_eq!(
+            error("-o -empty"),
+            "Invalid expression: expected an expression before \"-o\"\n    -o -empty\n    ^^"
+        );
+        assert_eq!(
+            error("-empty -type"),
+            "Invalid expression: missing argument to -type\n    -empty -type\n                 ^"
+        );
+        assert_eq!(
+            error("-size 10X"),
+            "Invalid expression: invalid argument \"10X\" to -size\n    -size 10X\n          ^^^"
+        );
+        assert_eq!(
+            error("-exec rm {}"),
+            "Invalid expression: missing \";\" or \"{} +\" after -exec\n    -exec rm {}\n    ^^^^^"
+        );
+        assert_eq!(
+            error("-empty -exec ;"),
+            "Invalid expression: missing command to -exec\n    -empty -exec ;\n           ^^^^^"
+        );
+    }
+}
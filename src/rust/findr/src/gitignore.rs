@@ -0,0 +1,149 @@
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::{
+    path::{self, Path, PathBuf},
+    sync::Arc,
+};
+use walkdir::DirEntry;
+
+/// `--gitignore`: what `.gitignore`, `.ignore` and `.git/info/exclude`
+/// files ignore inside one directory, the way git and fd read them. The
+/// rules of deeper directories win, and `.gitignore` files only count inside
+/// a git repository.
+pub struct Rules {
+    /// In order of precedence: `.ignore`, `.gitignore`, `.git/info/exclude`.
+    matchers: Vec<Gitignore>,
+    in_repo: bool,
+    /// The rules of the directory above, which apply here too.
+    parent: Option<Arc<Rules>>,
+}
+
+impl Rules {
+    /// Reads the rules for walking `root`: its own and those of its parents
+    /// up to the top of the repository it's in.
+    pub fn for_root(root: &Path) -> Arc<Rules> {
+        let root = absolute(root);
+        let parents: Vec<&Path> = root.ancestors().skip(1).collect();
+
+        let mut rules = None;
+        if let Some(top) = parents.iter().position(|dir| dir.join(".git").exists()) {
+            for dir in parents[..=top].iter().rev() {
+                rules = Some(Rules::read(dir, rules));
+            }
+        }
+        Rules::read(&root, rules)
+    }
+
+    /// Reads the rules inside `dir`, a directory these rules apply to.
+    pub fn enter(self: &Arc<Self>, dir: &Path) -> Arc<Rules> {
+        Rules::read(&absolute(dir), Some(Arc::clone(self)))
+    }
+
+    /// Returns whether `path` is left out, either for being hidden when
+    /// `hidden` files aren't searched or by the innermost rule matching it.
+    pub fn ignores(&self, path: &Path, is_dir: bool, hidden: bool) -> bool {
+        let is_hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if is_hidden && !hidden {
+            return true;
+        }
+
+        let path = absolute(path);
+        let mut rules = Some(self);
+        while let Some(current) = rules {
+            for matcher in &current.matchers {
+                match matcher.matched(&path, is_dir) {
+                    Match::None => {}
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                }
+            }
+            rules = current.parent.as_deref();
+        }
+        false
+    }
+
+    fn read(dir: &Path, parent: Option<Arc<Rules>>) -> Arc<Rules> {
+        let is_repo = dir.join(".git").exists();
+        let in_repo = is_repo || parent.as_ref().is_some_and(|rules| rules.in_repo);
+
+        let mut files = vec![dir.join(".ignore")];
+        if in_repo {
+            files.push(dir.join(".gitignore"));
+        }
+        if is_repo {
+            files.push(dir.join(".git").join("info").join("exclude"));
+        }
+
+        let matchers = files
+            .iter()
+            .filter(|file| file.is_file())
+            .filter_map(|file| {
+                let mut builder = GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(file) {
+                    eprintln!("{}", e);
+                }
+                builder.build().map_err(|e| eprintln!("{}", e)).ok()
+            })
+            .collect();
+
+        Arc::new(Rules {
+            matchers,
+            in_repo,
+            parent,
+        })
+    }
+}
+
+/// Applies [`Rules`] to a walk as `filter_entry`. Entries must be checked in
+/// walk order, as each directory's rules are kept only while its entries
+/// are being walked.
+pub struct IgnoreFilter {
+    hidden: bool,
+    /// The rules of the directories being walked, with their depths.
+    stack: Vec<(usize, Arc<Rules>)>,
+}
+
+impl IgnoreFilter {
+    pub fn new(root: &Path, hidden: bool) -> Self {
+        IgnoreFilter {
+            hidden,
+            stack: vec![(0, Rules::for_root(root))],
+        }
+    }
+
+    pub fn is_ignored(&mut self, entry: &DirEntry) -> bool {
+        // The starting points are always searched, and their rules are
+        // already read.
+        let depth = entry.depth();
+        if depth == 0 {
+            return false;
+        }
+
+        while self.stack.last().is_some_and(|(dir, _)| *dir >= depth) {
+            self.stack.pop();
+        }
+        let Some((_, rules)) = self.stack.last() else {
+            return false;
+        };
+
+        let is_dir = entry.file_type().is_dir();
+        if rules.ignores(entry.path(), is_dir, self.hidden) {
+            return true;
+        }
+        if is_dir {
+            let inner = rules.enter(entry.path());
+            self.stack.push((depth, inner));
+        }
+        false
+    }
+}
+
+/// Makes `path` absolute without resolving links, so it lines up with the
+/// directories the rules were read from.
+fn absolute(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -1,26 +1,146 @@
+use crate::action::Action;
+use crate::expr::{Candidate, Expr, Test};
+use crate::gitignore::IgnoreFilter;
+use crate::predicate::{AgeTest, PermTest, SizeTest};
+use crate::walk::{DepthFirst, Entry};
 use crate::EntryType::*;
 use clap::{App, Arg};
-use regex::Regex;
-use std::error::Error;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::{
+    cell::Cell,
+    env,
+    error::Error,
+    ffi::OsString,
+    fs::{self, FileType},
+    io::{self, BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 use walkdir::{DirEntry, WalkDir};
 
+mod action;
+mod expr;
+mod gitignore;
+mod predicate;
+mod walk;
+
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum EntryType {
     Dir,
     File,
     Link,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl EntryType {
+    /// The letters `--type` and `-type` take, in the order of the variants.
+    const LETTERS: [&'static str; 7] = ["d", "f", "l", "p", "s", "b", "c"];
+
+    fn from_letter(letter: &str) -> Option<EntryType> {
+        Some(match letter {
+            "d" => Dir,
+            "f" => File,
+            "l" => Link,
+            "p" => Fifo,
+            "s" => Socket,
+            "b" => BlockDevice,
+            "c" => CharDevice,
+            _ => return None,
+        })
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Dir => 'd',
+            File => 'f',
+            Link => 'l',
+            Fifo => 'p',
+            Socket => 's',
+            BlockDevice => 'b',
+            CharDevice => 'c',
+        }
+    }
+
+    /// Returns the type of `file_type`, if it's one find has a letter for.
+    fn of(file_type: FileType) -> Option<EntryType> {
+        if file_type.is_dir() {
+            return Some(Dir);
+        } else if file_type.is_file() {
+            return Some(File);
+        } else if file_type.is_symlink() {
+            return Some(Link);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if file_type.is_fifo() {
+                return Some(Fifo);
+            } else if file_type.is_socket() {
+                return Some(Socket);
+            } else if file_type.is_block_device() {
+                return Some(BlockDevice);
+            } else if file_type.is_char_device() {
+                return Some(CharDevice);
+            }
+        }
+        None
+    }
+}
+
+/// Which symlinks are walked as what they point to: find's `-P`, `-H` and
+/// `-L`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FollowLinks {
+    Never,
+    Roots,
+    Always,
 }
 
 #[derive(Debug)]
 pub struct Config {
-    paths: Vec<String>,
-    names: Vec<Regex>,
-    entry_types: Vec<EntryType>,
+    paths: Vec<PathBuf>,
+    expr: Expr,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    prune: GlobSet,
+    gitignore: bool,
+    hidden: bool,
+    jobs: usize,
+    sort: bool,
+    follow_links: FollowLinks,
+    xdev: bool,
 }
 
 pub fn get_args() -> MyResult<Config> {
+    // Like find, an expression such as `-name a -o ( -type d ! -empty )`
+    // follows the options and paths.
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let expr_start = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg.to_str().is_some_and(expr::is_expression_start))
+        .map_or(args.len(), |i| i + 1);
+    let expr_args = args
+        .split_off(expr_start)
+        .into_iter()
+        .map(|arg| {
+            arg.into_string().map_err(|arg| {
+                format!(
+                    "Invalid expression: argument \"{}\" isn't valid UTF-8",
+                    arg.to_string_lossy()
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
     let matches = App::new("findr")
         .version("0.1.0")
         .author("mysteryven <mysteryven@gmail.com>")
@@ -32,15 +152,163 @@ pub fn get_args() -> MyResult<Config> {
                 .long("name")
                 .takes_value(true)
                 .multiple(true)
-                .help("Name"),
+                .help("Name matches the shell glob NAME"),
+        )
+        .arg(
+            Arg::with_name("inames")
+                .value_name("NAME")
+                .long("iname")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Like --name, ignoring case"),
+        )
+        .arg(
+            Arg::with_name("path_globs")
+                .value_name("GLOB")
+                .long("path")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Whole path matches the shell glob GLOB"),
+        )
+        .arg(
+            Arg::with_name("regexes")
+                .value_name("REGEX")
+                .long("regex")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Whole path matches REGEX"),
+        )
+        .arg(
+            Arg::with_name("iregexes")
+                .value_name("REGEX")
+                .long("iregex")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Like --regex, ignoring case"),
         )
         .arg(
             Arg::with_name("types")
                 .value_name("TYPE")
                 .short("t")
                 .multiple(true)
-                .possible_values(&["f", "d", "l"])
-                .long("type"),
+                .possible_values(&EntryType::LETTERS)
+                .long("type")
+                .help("Entry type: d (directory), f (file), l (symlink), p (FIFO), s (socket), b (block device) or c (character device)"),
+        )
+        .arg(
+            Arg::with_name("sizes")
+                .value_name("SIZE")
+                .long("size")
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help("Uses more (+N), less (-N) or exactly N units of space: c, w, b (default), k, M or G"),
+        )
+        .arg(
+            Arg::with_name("mtimes")
+                .value_name("DAYS")
+                .long("mtime")
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help("Was modified more (+N), less (-N) or exactly N days ago"),
+        )
+        .arg(
+            Arg::with_name("newer")
+                .value_name("FILE")
+                .long("newer")
+                .help("Was modified more recently than FILE"),
+        )
+        .arg(
+            Arg::with_name("perm")
+                .value_name("MODE")
+                .long("perm")
+                .allow_hyphen_values(true)
+                .help("Has exactly the octal MODE, all of its bits (-MODE) or any of them (/MODE)"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .value_name("USER")
+                .long("user")
+                .help("Is owned by USER, a name or numeric ID"),
+        )
+        .arg(
+            Arg::with_name("group")
+                .value_name("GROUP")
+                .long("group")
+                .help("Belongs to GROUP, a name or numeric ID"),
+        )
+        .arg(
+            Arg::with_name("empty")
+                .long("empty")
+                .help("Is an empty file or directory"),
+        )
+        .arg(
+            Arg::with_name("max_depth")
+                .value_name("LEVELS")
+                .long("max-depth")
+                .help("Descend at most LEVELS below the starting points"),
+        )
+        .arg(
+            Arg::with_name("min_depth")
+                .value_name("LEVELS")
+                .long("min-depth")
+                .help("Ignore entries less than LEVELS below the starting points"),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .value_name("GLOB")
+                .long("prune")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Skip directories whose name matches GLOB, and everything in them"),
+        )
+        .arg(
+            Arg::with_name("gitignore")
+                .long("gitignore")
+                .help("Skip hidden files and what .gitignore, .ignore and .git/info/exclude files ignore"),
+        )
+        .arg(
+            Arg::with_name("hidden")
+                .long("hidden")
+                .requires("gitignore")
+                .help("With --gitignore, search hidden files too"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .value_name("N")
+                .short("j")
+                .long("jobs")
+                .default_value("1")
+                .help("Read directories on N threads; entries are found in no set order unless --sort is given"),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .help("Visit entries in path order, so the output is the same every run"),
+        )
+        .arg(
+            Arg::with_name("physical")
+                .short("P")
+                .overrides_with_all(&["command_line", "logical"])
+                .help("Never follow symlinks (the default)"),
+        )
+        .arg(
+            Arg::with_name("command_line")
+                .short("H")
+                .overrides_with_all(&["physical", "logical"])
+                .help("Follow symlinks given as paths, but no others"),
+        )
+        .arg(
+            Arg::with_name("logical")
+                .short("L")
+                .overrides_with_all(&["physical", "command_line"])
+                .help("Follow all symlinks, warning about any that loop"),
+        )
+        .arg(
+            Arg::with_name("xdev")
+                .long("xdev")
+                .help("Don't descend into directories on other filesystems"),
         )
         .arg(
             Arg::with_name("paths")
@@ -48,74 +316,257 @@ pub fn get_args() -> MyResult<Config> {
                 .default_value(".")
                 .multiple(true),
         )
-        .get_matches();
+        .after_help(
+            "An EXPRESSION may follow the paths, built from -name, -iname, -path, -regex, \
+             -iregex, -type, -xtype, -size, -mtime, -newer, -perm, -user, -group and -empty \
+             with ( ), ! or -not, -a or -and, and -o or -or. It must match along with any \
+             options. \
+             Its actions -print, -print0, -printf FORMAT, -delete, -exec COMMAND ; and \
+             -exec COMMAND {} + run on the entries they're reached for; without any, \
+             matching paths are printed. -xdev there is the same as --xdev.",
+        )
+        .get_matches_from(args);
 
-    let paths = matches.values_of_lossy("paths").unwrap();
+    let paths = matches
+        .values_of_os("paths")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
 
-    let names = matches
-        .values_of_lossy("names")
-        .map(|vals| {
-            vals.into_iter()
-                .map(|name| Regex::new(&name).map_err(|_| format!("Invalid --name \"{}\"", name)))
-                .collect::<Result<Vec<_>, _>>()
-        })
-        .transpose()?
-        .unwrap_or_default();
+    // Several values of one option match if any does; different options
+    // must all match.
+    let patterns = |name: &str, flag: &str, test: &dyn Fn(&str) -> MyResult<Test>| {
+        matches
+            .values_of_lossy(name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|val| {
+                test(&val)
+                    .map(Expr::Test)
+                    .map_err(|_| format!("Invalid --{} \"{}\"", flag, val))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let names = patterns("names", "name", &|val| {
+        Ok(Test::Name(expr::glob(val, false)?))
+    })?;
+    let inames = patterns("inames", "iname", &|val| {
+        Ok(Test::Name(expr::glob(val, true)?))
+    })?;
+    let path_globs = patterns("path_globs", "path", &|val| {
+        Ok(Test::Path(expr::glob(val, false)?))
+    })?;
+    let regexes = patterns("regexes", "regex", &|val| {
+        Ok(Test::Regex(expr::regex(val, false)?))
+    })?;
+    let iregexes = patterns("iregexes", "iregex", &|val| {
+        Ok(Test::Regex(expr::regex(val, true)?))
+    })?;
 
     let entry_types = matches
         .values_of_lossy("types")
-        .map(|vals| {
-            vals.into_iter()
-                .map(|val| match val.as_str() {
-                    "f" => File,
-                    "d" => Dir,
-                    "l" => Link,
-                    _ => unreachable!("Invalid type"),
-                })
-                .collect()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|val| {
+            let entry_type =
+                EntryType::from_letter(&val).unwrap_or_else(|| unreachable!("Invalid type"));
+            Expr::Test(Test::Type(entry_type))
+        });
+
+    let mut tests = vec![
+        Expr::any(names),
+        Expr::any(inames),
+        Expr::any(path_globs),
+        Expr::any(regexes),
+        Expr::any(iregexes),
+        Expr::any(entry_types),
+    ];
+
+    for val in matches.values_of_lossy("sizes").unwrap_or_default() {
+        tests.push(Expr::Test(Test::Size(SizeTest::parse(&val)?)));
+    }
+    for val in matches.values_of_lossy("mtimes").unwrap_or_default() {
+        tests.push(Expr::Test(Test::Mtime(AgeTest::parse(&val)?)));
+    }
+    if let Some(file) = matches.value_of("newer") {
+        let time = fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("{}: {}", file, e))?;
+        tests.push(Expr::Test(Test::Newer(time)));
+    }
+    if let Some(val) = matches.value_of("perm") {
+        tests.push(Expr::Test(Test::Perm(PermTest::parse(val)?)));
+    }
+    if let Some(val) = matches.value_of("user") {
+        tests.push(Expr::Test(Test::User(predicate::parse_user(val)?)));
+    }
+    if let Some(val) = matches.value_of("group") {
+        tests.push(Expr::Test(Test::Group(predicate::parse_group(val)?)));
+    }
+    if matches.is_present("empty") {
+        tests.push(Expr::Test(Test::Empty));
+    }
+    let (expr, options) = expr::parse(&expr_args)?;
+    tests.push(expr);
+
+    let parse_depth = |name: &str, flag: &str| {
+        matches
+            .value_of(name)
+            .map(|val| {
+                val.parse::<usize>()
+                    .map_err(|_| format!("Invalid --{} \"{}\"", flag, val))
+            })
+            .transpose()
+    };
+    let min_depth = parse_depth("min_depth", "min-depth")?.unwrap_or(0);
+    let max_depth = parse_depth("max_depth", "max-depth")?;
+
+    let mut prune = GlobSetBuilder::new();
+    for glob in matches.values_of_lossy("prune").unwrap_or_default() {
+        prune.add(Glob::new(&glob).map_err(|_| format!("Invalid --prune \"{}\"", glob))?);
+    }
+
+    let jobs = matches
+        .value_of("jobs")
+        .map(|val| match val.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("Invalid --jobs \"{}\"", val)),
         })
-        .unwrap_or_default();
+        .transpose()?
+        .unwrap_or(1);
+
+    // Like find, an expression without actions prints what it matches.
+    let mut expr = tests.into_iter().fold(Expr::True, Expr::and);
+    if !expr.any_action(&|_| true) {
+        expr = expr.and(Expr::Action(Action::Print));
+    }
 
     Ok(Config {
         paths,
-        names,
-        entry_types,
+        expr,
+        min_depth,
+        max_depth,
+        prune: prune.build()?,
+        gitignore: matches.is_present("gitignore"),
+        hidden: matches.is_present("hidden"),
+        jobs,
+        sort: matches.is_present("sort"),
+        follow_links: if matches.is_present("logical") {
+            FollowLinks::Always
+        } else if matches.is_present("command_line") {
+            FollowLinks::Roots
+        } else {
+            FollowLinks::Never
+        },
+        xdev: matches.is_present("xdev") || options.xdev,
     })
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let type_filter = |entry: &DirEntry| {
-        config.entry_types.is_empty()
-            || config
-                .entry_types
-                .iter()
-                .any(|entry_type| match entry_type {
-                    Dir => entry.file_type().is_dir(),
-                    File => entry.file_type().is_file(),
-                    Link => entry.file_type().is_symlink(),
-                })
-    };
+/// Walks every path, returning `false` if an entry couldn't be read, a link
+/// looped or a batched `-exec` command failed.
+pub fn run(mut config: Config) -> MyResult<bool> {
+    let now = SystemTime::now();
+    // Matches are written as they're found; the buffer is flushed before
+    // any command runs so its output comes after them.
+    let mut out = BufWriter::new(io::stdout().lock());
+    // The expression runs actions, so it's taken out of the config the
+    // walks share.
+    let mut expr = mem::replace(&mut config.expr, Expr::True);
+    // Deleting a directory needs its contents gone first, which only the
+    // sequential walk can promise.
+    let depth_first = expr.any_action(&|action| matches!(action, Action::Delete { .. }));
+    let parallel = config.jobs > 1 && !depth_first;
+    // Like find, errors during a walk are reported and skipped, but make it
+    // fail in the end.
+    let walk_failed = Cell::new(false);
 
-    let name_filter = |entry: &DirEntry| {
-        config.names.is_empty()
-            || config
-                .names
-                .iter()
-                .any(|re| re.is_match(&entry.file_name().to_string_lossy()))
-    };
+    for path in &config.paths {
+        let mut visit = |entry: Entry| {
+            if entry.depth() >= config.min_depth {
+                expr.eval(&Candidate::new(&entry, path, now), &mut out)?;
+            }
+            Ok::<_, Box<dyn Error>>(())
+        };
 
+        if parallel {
+            let mut sorted = vec![];
+            walk::walk_parallel(path, &config, |found| {
+                match found {
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        walk_failed.set(true);
+                    }
+                    Ok(entry) if config.sort => sorted.push(entry),
+                    Ok(entry) => visit(entry)?,
+                }
+                Ok::<_, Box<dyn Error>>(())
+            })?;
 
-    for path in &config.paths {
-        let entries = WalkDir::new(path).into_iter().filter_map(|e| match e {
-            Err(e) => {
-                eprintln!("{}", e);
-                None
-            },
-            Ok(entry) => Some(entry)
-        }).filter(type_filter).filter(name_filter).map(|entry| entry.path().display().to_string()).collect::<Vec<_>>();
+            // Comparing paths component by component puts each directory
+            // before its contents and its entries in name order, as the
+            // sorted sequential walk visits them.
+            sorted.sort_by(|a, b| a.path().cmp(b.path()));
+            for entry in sorted {
+                visit(entry)?;
+            }
+        } else {
+            let walk = walk_sequential(path, &config, &walk_failed);
+            for entry in DepthFirst::new(walk, depth_first) {
+                visit(entry)?;
+            }
+        }
+    }
+
+    let succeeded = expr.finish(&mut out)?;
+    out.flush()?;
+    Ok(succeeded && !walk_failed.get())
+}
 
-        println!("{}", entries.join("\n"))
+/// Walks `root` on this thread, in name order with `--sort`. Errors are
+/// reported as they come and set `failed`.
+fn walk_sequential<'a>(
+    root: &Path,
+    config: &'a Config,
+    failed: &'a Cell<bool>,
+) -> impl Iterator<Item = Entry> + 'a {
+    // Pruned directories are never read. This is done before the minimum
+    // depth is applied, as walkdir doesn't pass the entries it skips for
+    // min_depth to filter_entry.
+    let prune_filter = |entry: &DirEntry| {
+        !(entry.file_type().is_dir() && config.prune.is_match(entry.file_name()))
+    };
+    let mut ignore = config
+        .gitignore
+        .then(|| IgnoreFilter::new(root, config.hidden));
+
+    // walkdir reports links that loop back to a directory it's in as
+    // errors, rather than walking them forever.
+    let mut walker = WalkDir::new(root)
+        .max_depth(config.max_depth.unwrap_or(usize::MAX))
+        .follow_links(config.follow_links == FollowLinks::Always)
+        .follow_root_links(config.follow_links != FollowLinks::Never)
+        .same_file_system(config.xdev);
+    if config.sort {
+        walker = walker.sort_by_file_name();
     }
 
-    Ok(())
+    walker
+        .into_iter()
+        .filter_entry(move |entry| {
+            prune_filter(entry)
+                && !ignore
+                    .as_mut()
+                    .is_some_and(|ignore| ignore.is_ignored(entry))
+        })
+        .filter_map(|e| match e {
+            Err(e) => Entry::from_broken_link(&e).or_else(|| {
+                eprintln!("{}", e);
+                failed.set(true);
+                None
+            }),
+            Ok(entry) => {
+                let follow = config.follow_links.at(entry.depth());
+                Some(Entry::from_walkdir(entry, follow))
+            }
+        })
 }
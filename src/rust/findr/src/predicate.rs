@@ -0,0 +1,237 @@
+use crate::MyResult;
+use std::{
+    cmp::Ordering,
+    fs::{self, Metadata},
+    path::Path,
+    time::SystemTime,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A number written the way find does: `+N` for more than N, `-N` for less
+/// than N and `N` for exactly N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumTest {
+    ord: Ordering,
+    value: u64,
+}
+
+impl NumTest {
+    /// Parses the leading number of `val`, returning it with the rest.
+    fn parse(val: &str) -> Option<(NumTest, &str)> {
+        let (ord, rest) = match val.as_bytes().first() {
+            Some(b'+') => (Ordering::Greater, &val[1..]),
+            Some(b'-') => (Ordering::Less, &val[1..]),
+            _ => (Ordering::Equal, val),
+        };
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let value = rest[..digits].parse().ok()?;
+        Some((NumTest { ord, value }, &rest[digits..]))
+    }
+
+    pub fn matches(&self, n: u64) -> bool {
+        n.cmp(&self.value) == self.ord
+    }
+}
+
+/// `--size`: the space a file uses, rounded up to whole units like find, so
+/// `-1M` only matches empty files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeTest {
+    test: NumTest,
+    unit: u64,
+}
+
+impl SizeTest {
+    pub fn parse(val: &str) -> MyResult<SizeTest> {
+        let unit = |suffix| match suffix {
+            "c" => Some(1),
+            "w" => Some(2),
+            "b" | "" => Some(512),
+            "k" => Some(1 << 10),
+            "M" => Some(1 << 20),
+            "G" => Some(1 << 30),
+            _ => None,
+        };
+
+        NumTest::parse(val)
+            .and_then(|(test, suffix)| {
+                Some(SizeTest {
+                    test,
+                    unit: unit(suffix)?,
+                })
+            })
+            .ok_or_else(|| format!("Invalid --size \"{}\"", val).into())
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.test.matches(metadata.len().div_ceil(self.unit))
+    }
+}
+
+/// `--mtime`: whole days since the last modification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgeTest(NumTest);
+
+impl AgeTest {
+    pub fn parse(val: &str) -> MyResult<AgeTest> {
+        match NumTest::parse(val) {
+            Some((test, "")) => Ok(AgeTest(test)),
+            _ => Err(format!("Invalid --mtime \"{}\"", val).into()),
+        }
+    }
+
+    pub fn matches(&self, metadata: &Metadata, now: SystemTime) -> bool {
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        self.0.matches(age.as_secs() / SECONDS_PER_DAY)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PermMatch {
+    Exact,
+    All,
+    Any,
+}
+
+/// `--perm`: an octal mode that must match exactly, or with a `-` prefix
+/// have all of its bits set, or with a `/` prefix have any of them set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermTest {
+    mode: u32,
+    kind: PermMatch,
+}
+
+impl PermTest {
+    pub fn parse(val: &str) -> MyResult<PermTest> {
+        let (kind, digits) = match val.as_bytes().first() {
+            Some(b'-') => (PermMatch::All, &val[1..]),
+            Some(b'/') => (PermMatch::Any, &val[1..]),
+            _ => (PermMatch::Exact, val),
+        };
+
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o7777 && !digits.starts_with('+') => Ok(PermTest { mode, kind }),
+            _ => Err(format!("Invalid --perm \"{}\"", val).into()),
+        }
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let Some(mode) = mode(metadata) else {
+            return false;
+        };
+
+        match self.kind {
+            PermMatch::Exact => mode == self.mode,
+            PermMatch::All => mode & self.mode == self.mode,
+            PermMatch::Any => self.mode == 0 || mode & self.mode != 0,
+        }
+    }
+}
+
+/// Returns the permission bits, including setuid, setgid and sticky.
+#[cfg(unix)]
+pub fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Returns the owner's user and group IDs.
+#[cfg(unix)]
+pub fn owner(metadata: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner(_metadata: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// Looks up `--user`, given as a name or a numeric ID.
+pub fn parse_user(val: &str) -> MyResult<u32> {
+    #[cfg(unix)]
+    let by_name = users::get_user_by_name(val).map(|user| user.uid());
+    #[cfg(not(unix))]
+    let by_name = None;
+
+    val.parse()
+        .ok()
+        .or(by_name)
+        .ok_or_else(|| format!("Invalid --user \"{}\"", val).into())
+}
+
+/// Looks up `--group`, given as a name or a numeric ID.
+pub fn parse_group(val: &str) -> MyResult<u32> {
+    #[cfg(unix)]
+    let by_name = users::get_group_by_name(val).map(|group| group.gid());
+    #[cfg(not(unix))]
+    let by_name = None;
+
+    val.parse()
+        .ok()
+        .or(by_name)
+        .ok_or_else(|| format!("Invalid --group \"{}\"", val).into())
+}
+
+/// `--empty`: an empty regular file or a directory with no entries.
+pub fn is_empty(path: &Path, metadata: &Metadata) -> bool {
+    if metadata.is_dir() {
+        fs::read_dir(path)
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(false)
+    } else {
+        metadata.is_file() && metadata.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NumTest, PermTest, SizeTest};
+
+    #[test]
+    fn test_num_test() {
+        let (test, rest) = NumTest::parse("+10M").unwrap();
+        assert_eq!(rest, "M");
+        assert!(test.matches(11));
+        assert!(!test.matches(10));
+
+        let (test, _) = NumTest::parse("-7").unwrap();
+        assert!(test.matches(6));
+        assert!(!test.matches(7));
+
+        let (test, _) = NumTest::parse("3").unwrap();
+        assert!(test.matches(3));
+        assert!(NumTest::parse("+").is_none());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert!(SizeTest::parse("+10M").is_ok());
+        assert!(SizeTest::parse("20c").is_ok());
+        assert!(SizeTest::parse("-3").is_ok());
+        assert!(SizeTest::parse("10X").is_err());
+        assert!(SizeTest::parse("M").is_err());
+    }
+
+    #[test]
+    fn test_parse_perm() {
+        assert!(PermTest::parse("644").is_ok());
+        assert!(PermTest::parse("-111").is_ok());
+        assert!(PermTest::parse("/022").is_ok());
+        assert!(PermTest::parse("8").is_err());
+        assert!(PermTest::parse("17777").is_err());
+        assert!(PermTest::parse("u+x").is_err());
+    }
+}
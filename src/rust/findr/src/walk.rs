@@ -0,0 +1,421 @@
+use crate::{gitignore::Rules, Config, FollowLinks};
+use same_file::Handle;
+use std::{
+    ffi::OsStr,
+    fs::{self, FileType, Metadata},
+    io,
+    iter::Fuse,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+};
+
+/// An entry found by either walk.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    path: PathBuf,
+    depth: usize,
+    file_type: FileType,
+    /// Whether a symlink here stands for what it points to.
+    follow: bool,
+}
+
+impl Entry {
+    /// When `follow` is set, a symlink takes the type of what it points
+    /// to, unless that's missing.
+    fn new(path: PathBuf, depth: usize, mut file_type: FileType, follow: bool) -> Self {
+        if follow && file_type.is_symlink() {
+            if let Ok(target) = fs::metadata(&path) {
+                file_type = target.file_type();
+            }
+        }
+
+        Entry {
+            path,
+            depth,
+            file_type,
+            follow,
+        }
+    }
+
+    pub fn from_walkdir(entry: walkdir::DirEntry, follow: bool) -> Self {
+        let (depth, file_type) = (entry.depth(), entry.file_type());
+        Entry::new(entry.into_path(), depth, file_type, follow)
+    }
+
+    /// Makes an entry of a followed symlink whose target is missing, which
+    /// walkdir reports as an error but find lists as a link.
+    pub fn from_broken_link(e: &walkdir::Error) -> Option<Self> {
+        let path = e.path()?;
+        let metadata = fs::symlink_metadata(path).ok()?;
+        if !metadata.file_type().is_symlink() || fs::metadata(path).is_ok() {
+            return None;
+        }
+        Some(Entry::new(
+            path.to_path_buf(),
+            e.depth(),
+            metadata.file_type(),
+            true,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The last component of the path, or the whole path when it has none,
+    /// as with `.`.
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// The type `-xtype` tests: for a symlink that isn't followed, the type
+    /// of what it points to, and for one that is, the link itself. A broken
+    /// link stays a link.
+    pub fn other_type(&self) -> FileType {
+        let other = if self.follow {
+            fs::symlink_metadata(&self.path)
+        } else if self.file_type.is_symlink() {
+            fs::metadata(&self.path)
+        } else {
+            return self.file_type;
+        };
+        other.map_or(self.file_type, |metadata| metadata.file_type())
+    }
+
+    /// Reads the metadata of what a followed symlink points to, or of the
+    /// link itself when it's broken or not followed.
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        if self.follow {
+            fs::metadata(&self.path).or_else(|_| fs::symlink_metadata(&self.path))
+        } else {
+            fs::symlink_metadata(&self.path)
+        }
+    }
+}
+
+impl FollowLinks {
+    /// Returns whether symlinks are followed at `depth`.
+    pub fn at(self, depth: usize) -> bool {
+        match self {
+            FollowLinks::Never => false,
+            FollowLinks::Roots => depth == 0,
+            FollowLinks::Always => true,
+        }
+    }
+}
+
+/// Holds each directory of a walk back until its contents have been
+/// yielded, like find's `-depth`. The walk itself stays in pre-order, so
+/// `filter_entry` still sees a directory before deciding to skip it.
+pub struct DepthFirst<I: Iterator<Item = Entry>> {
+    entries: Fuse<I>,
+    enabled: bool,
+    next: Option<Entry>,
+    dirs: Vec<Entry>,
+}
+
+impl<I: Iterator<Item = Entry>> DepthFirst<I> {
+    /// When `enabled` is false the entries pass through unchanged.
+    pub fn new(entries: I, enabled: bool) -> Self {
+        DepthFirst {
+            entries: entries.fuse(),
+            enabled,
+            next: None,
+            dirs: vec![],
+        }
+    }
+}
+
+impl<I: Iterator<Item = Entry>> Iterator for DepthFirst<I> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if !self.enabled {
+            return self.entries.next();
+        }
+
+        loop {
+            if self.next.is_none() {
+                self.next = self.entries.next();
+            }
+
+            // A held directory is done once the walk leaves it.
+            match &self.next {
+                Some(entry)
+                    if self
+                        .dirs
+                        .last()
+                        .is_none_or(|dir| entry.depth() > dir.depth()) =>
+                {
+                    let entry = self.next.take()?;
+                    if !entry.file_type().is_dir() {
+                        return Some(entry);
+                    }
+                    self.dirs.push(entry);
+                }
+                _ => return self.dirs.pop(),
+            }
+        }
+    }
+}
+
+/// A directory waiting to be read, with the ignore rules inside it.
+struct Dir {
+    path: PathBuf,
+    depth: usize,
+    rules: Option<Arc<Rules>>,
+    /// The directory and those above it, kept while following links to
+    /// notice a link back to one of them.
+    ancestors: Option<Arc<Ancestor>>,
+}
+
+struct Ancestor {
+    path: PathBuf,
+    handle: Handle,
+    parent: Option<Arc<Ancestor>>,
+}
+
+impl Ancestor {
+    /// Returns the path of the directory in the chain that `handle` is.
+    fn find(&self, handle: &Handle) -> Option<&Path> {
+        let mut ancestor = Some(self);
+        while let Some(current) = ancestor {
+            if current.handle == *handle {
+                return Some(&current.path);
+            }
+            ancestor = current.parent.as_deref();
+        }
+        None
+    }
+}
+
+/// The directories waiting to be read, and how many threads are reading
+/// one (and so may add more).
+struct Queue {
+    dirs: Vec<Dir>,
+    busy: usize,
+}
+
+/// Walks `root` reading directories on `config.jobs` threads, skipping what
+/// the sequential walk skips. Entries and errors are passed to `visit` on
+/// the calling thread as they're found, so their order varies from run to
+/// run; the walk stops if `visit` fails.
+pub fn walk_parallel<E>(
+    root: &Path,
+    config: &Config,
+    mut visit: impl FnMut(Result<Entry, String>) -> Result<(), E>,
+) -> Result<(), E> {
+    let path = root.to_path_buf();
+    let follow = config.follow_links.at(0);
+    let entry = match fs::symlink_metadata(&path) {
+        Ok(metadata) => Entry::new(path, 0, metadata.file_type(), follow),
+        Err(e) => return visit(Err(walk_error(&path, &e))),
+    };
+
+    let walk = Walk {
+        config,
+        max_depth: config.max_depth.unwrap_or(usize::MAX),
+        device: config
+            .xdev
+            .then(|| fs::metadata(entry.path()).ok().and_then(|m| device(&m)))
+            .flatten(),
+    };
+    let queue = Mutex::new(Queue {
+        dirs: vec![],
+        busy: 0,
+    });
+    if entry.file_type().is_dir() && walk.max_depth > 0 {
+        let ancestors = match config.follow_links {
+            FollowLinks::Always => Handle::from_path(entry.path()).ok().map(|handle| {
+                Arc::new(Ancestor {
+                    path: entry.path().to_path_buf(),
+                    handle,
+                    parent: None,
+                })
+            }),
+            _ => None,
+        };
+        queue.lock().unwrap().dirs.push(Dir {
+            path: entry.path().to_path_buf(),
+            depth: 0,
+            rules: config.gitignore.then(|| Rules::for_root(entry.path())),
+            ancestors,
+        });
+    }
+    visit(Ok(entry))?;
+
+    let ready = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..config.jobs {
+            let (tx, queue, ready, walk) = (tx.clone(), &queue, &ready, &walk);
+            scope.spawn(move || loop {
+                let mut state = queue.lock().unwrap();
+                let dir = loop {
+                    if let Some(dir) = state.dirs.pop() {
+                        break dir;
+                    }
+                    if state.busy == 0 {
+                        return;
+                    }
+                    state = ready.wait(state).unwrap();
+                };
+                state.busy += 1;
+                drop(state);
+
+                let (subdirs, sent) = walk.read_dir(&dir, &tx);
+
+                let mut state = queue.lock().unwrap();
+                state.busy -= 1;
+                if sent {
+                    state.dirs.extend(subdirs);
+                } else {
+                    // Nothing is listening any more.
+                    state.dirs.clear();
+                }
+                ready.notify_all();
+            });
+        }
+        drop(tx);
+
+        for found in rx {
+            visit(found)?;
+        }
+        Ok(())
+    })
+}
+
+/// What every thread of a parallel walk needs to know.
+struct Walk<'a> {
+    config: &'a Config,
+    max_depth: usize,
+    /// With `--xdev`, the device of the starting point.
+    device: Option<u64>,
+}
+
+impl Walk<'_> {
+    /// Sends the entries of `dir` that aren't pruned or ignored, returning
+    /// the subdirectories to read next and whether everything could be
+    /// sent.
+    fn read_dir(&self, dir: &Dir, tx: &mpsc::Sender<Result<Entry, String>>) -> (Vec<Dir>, bool) {
+        let mut subdirs = vec![];
+        let children = match fs::read_dir(&dir.path) {
+            Ok(children) => children,
+            Err(e) => return (subdirs, tx.send(Err(walk_error(&dir.path, &e))).is_ok()),
+        };
+
+        let follow = self.config.follow_links.at(dir.depth + 1);
+        for child in children {
+            let found = child.and_then(|child| {
+                Ok(Entry::new(
+                    child.path(),
+                    dir.depth + 1,
+                    child.file_type()?,
+                    follow,
+                ))
+            });
+            let found = found
+                .map_err(|e| walk_error(&dir.path, &e))
+                .and_then(|entry| self.check_loop(dir, entry));
+            let entry = match found {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if tx.send(Err(e)).is_err() {
+                        return (subdirs, false);
+                    }
+                    continue;
+                }
+            };
+
+            let is_dir = entry.file_type().is_dir();
+            if is_dir && self.config.prune.is_match(entry.file_name()) {
+                continue;
+            }
+            if let Some(rules) = &dir.rules {
+                if rules.ignores(entry.path(), is_dir, self.config.hidden) {
+                    continue;
+                }
+            }
+
+            if is_dir && entry.depth() < self.max_depth && self.same_device(&entry) {
+                subdirs.push(Dir {
+                    path: entry.path().to_path_buf(),
+                    depth: entry.depth(),
+                    rules: dir.rules.as_ref().map(|rules| rules.enter(entry.path())),
+                    ancestors: dir.ancestors.as_ref().and_then(|parent| {
+                        Some(Arc::new(Ancestor {
+                            path: entry.path().to_path_buf(),
+                            handle: Handle::from_path(entry.path()).ok()?,
+                            parent: Some(Arc::clone(parent)),
+                        }))
+                    }),
+                });
+            }
+            if tx.send(Ok(entry)).is_err() {
+                return (subdirs, false);
+            }
+        }
+
+        (subdirs, true)
+    }
+
+    /// Fails on a followed link to a directory the walk is already in,
+    /// which would otherwise be walked forever.
+    fn check_loop(&self, dir: &Dir, entry: Entry) -> Result<Entry, String> {
+        let Some(ancestors) = &dir.ancestors else {
+            return Ok(entry);
+        };
+        if !entry.file_type().is_dir() || !entry.path().is_symlink() {
+            return Ok(entry);
+        }
+
+        let handle = Handle::from_path(entry.path()).map_err(|e| walk_error(entry.path(), &e))?;
+        match ancestors.find(&handle) {
+            Some(ancestor) => Err(format!(
+                "File system loop found: {} points to an ancestor {}",
+                entry.path().display(),
+                ancestor.display()
+            )),
+            None => Ok(entry),
+        }
+    }
+
+    /// With `--xdev`, returns whether `entry` is on the starting point's
+    /// device, and so may be walked into.
+    fn same_device(&self, entry: &Entry) -> bool {
+        let Some(device) = self.device else {
+            return true;
+        };
+        entry
+            .metadata()
+            .ok()
+            .and_then(|metadata| self::device(&metadata))
+            .is_none_or(|other| other == device)
+    }
+}
+
+#[cfg(unix)]
+fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Describes a failure the way walkdir does, so both walks report alike.
+fn walk_error(path: &Path, e: &io::Error) -> String {
+    format!("IO error for operation on {}: {}", path.display(), e)
+}
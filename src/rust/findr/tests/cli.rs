@@ -1,7 +1,14 @@
 use assert_cmd::Command;
+use filetime::FileTime;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::{borrow::Cow, fs, path::Path};
+use std::{
+    borrow::Cow,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tempfile::TempDir;
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -30,24 +37,26 @@ fn skips_bad_dir() -> TestResult {
     Command::cargo_bin(PRG)?
         .arg(&bad)
         .assert()
-        .success()
+        .failure()
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
 
 // --------------------------------------------------
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn dies_bad_name() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["--name", "*.csv"])
+        .args(&["--name", "a[b"])
         .assert()
         .failure()
-        .stderr(predicate::str::contains("Invalid --name \"*.csv\""));
+        .stderr(predicate::str::contains("Invalid --name \"a[b\""));
     Ok(())
 }
 
 // --------------------------------------------------
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn dies_bad_type() -> TestResult {
     let expected = "error: 'x' isn't a valid value for '--type <TYPE>...'";
     Command::cargo_bin(PRG)?
@@ -60,6 +69,7 @@ fn dies_bad_type() -> TestResult {
 
 // --------------------------------------------------
 #[cfg(windows)]
+#[allow(mismatched_lifetime_syntaxes)]
 fn format_file_name(expected_file: &str) -> Cow<str> {
     // Equivalent to: Cow::Owned(format!("{}.windows", expected_file))
     format!("{}.windows", expected_file).into()
@@ -67,6 +77,7 @@ fn format_file_name(expected_file: &str) -> Cow<str> {
 
 // --------------------------------------------------
 #[cfg(not(windows))]
+#[allow(mismatched_lifetime_syntaxes)]
 fn format_file_name(expected_file: &str) -> Cow<str> {
     // Equivalent to: Cow::Borrowed(expected_file)
     expected_file.into()
@@ -228,7 +239,7 @@ fn type_f_l() -> TestResult {
 #[test]
 fn name_csv() -> TestResult {
     run(
-        &["tests/inputs", "-n", ".*[.]csv"],
+        &["tests/inputs", "-n", "*.csv"],
         "tests/expected/name_csv.txt",
     )
 }
@@ -237,7 +248,7 @@ fn name_csv() -> TestResult {
 #[test]
 fn name_csv_mp3() -> TestResult {
     run(
-        &["tests/inputs", "-n", ".*[.]csv", "-n", ".*[.]mp3"],
+        &["tests/inputs", "-n", "*.csv", "-n", "*.mp3"],
         "tests/expected/name_csv_mp3.txt",
     )
 }
@@ -246,7 +257,7 @@ fn name_csv_mp3() -> TestResult {
 #[test]
 fn name_txt_path_a_d() -> TestResult {
     run(
-        &["tests/inputs/a", "tests/inputs/d", "--name", ".*.txt"],
+        &["tests/inputs/a", "tests/inputs/d", "--name", "*.txt"],
         "tests/expected/name_txt_path_a_d.txt",
     )
 }
@@ -254,14 +265,14 @@ fn name_txt_path_a_d() -> TestResult {
 // --------------------------------------------------
 #[test]
 fn name_a() -> TestResult {
-    run(&["tests/inputs", "-n", "a"], "tests/expected/name_a.txt")
+    run(&["tests/inputs", "-n", "*a*"], "tests/expected/name_a.txt")
 }
 
 // --------------------------------------------------
 #[test]
 fn type_f_name_a() -> TestResult {
     run(
-        &["tests/inputs", "-t", "f", "-n", "a"],
+        &["tests/inputs", "-t", "f", "-n", "*a*"],
         "tests/expected/type_f_name_a.txt",
     )
 }
@@ -270,7 +281,7 @@ fn type_f_name_a() -> TestResult {
 #[test]
 fn type_d_name_a() -> TestResult {
     run(
-        &["tests/inputs", "--type", "d", "--name", "a"],
+        &["tests/inputs", "--type", "d", "--name", "*a*"],
         "tests/expected/type_d_name_a.txt",
     )
 }
@@ -284,6 +295,7 @@ fn path_g() -> TestResult {
 // --------------------------------------------------
 #[test]
 #[cfg(not(windows))]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn unreadable_dir() -> TestResult {
     let dirname = "tests/inputs/cant-touch-this";
     if !Path::new(dirname).exists() {
@@ -301,9 +313,9 @@ fn unreadable_dir() -> TestResult {
 
     let cmd = Command::cargo_bin(PRG)?
         .arg("tests/inputs")
-        .assert()
-        .success();
+        .assert();
     fs::remove_dir(dirname)?;
+    let cmd = cmd.failure();
 
     let out = cmd.get_output();
     let stdout = String::from_utf8(out.stdout.clone())?;
@@ -316,3 +328,1106 @@ fn unreadable_dir() -> TestResult {
     assert!(stderr.contains("cant-touch-this: Permission denied"));
     Ok(())
 }
+
+// --------------------------------------------------
+// git keeps neither modification times nor most permission bits, and tests
+// run in parallel, so each test gets its own copy of the files in h with
+// theirs set. Paths in the expected output are relative to the copy.
+fn set_up_h() -> Result<TempDir, Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let h = dir.path().join("h");
+    fs::create_dir(&h)?;
+
+    let now = SystemTime::now();
+    let day = Duration::from_secs(24 * 60 * 60);
+
+    for (name, contents, days_old, mode) in [
+        ("big.bin", "x".repeat(3000), 10, 0o755),
+        ("old.log", "o".repeat(100), 30, 0o600),
+        ("new.log", "new file\n\n".to_string(), 0, 0o644),
+        ("empty", String::new(), 3, 0o644),
+    ] {
+        let path = h.join(name);
+        fs::write(&path, contents)?;
+        let mtime = FileTime::from_system_time(now - day * days_old);
+        filetime::set_file_mtime(&path, mtime)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+    }
+
+    Ok(dir)
+}
+
+// --------------------------------------------------
+fn run_h(args: &[&str], expected_file: &str) -> TestResult {
+    let dir = set_up_h()?;
+    let file = format_file_name(expected_file);
+    let contents = fs::read_to_string(file.as_ref())?;
+    let mut expected: Vec<&str> = contents.lines().collect();
+    expected.sort();
+
+    let cmd = Command::cargo_bin(PRG)?
+        .current_dir(&dir)
+        .arg("h")
+        .args(args)
+        .assert()
+        .success();
+    let stdout = String::from_utf8(cmd.get_output().stdout.clone())?;
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+
+    assert_eq!(lines, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn size_plus_2k() -> TestResult {
+    run_h(
+        &["--size", "+2k", "-t", "f"],
+        "tests/expected/size_plus_2k.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_minus_1k() -> TestResult {
+    run_h(
+        &["--size", "-1k", "-t", "f"],
+        "tests/expected/size_minus_1k.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_100c() -> TestResult {
+    run_h(&["--size", "100c"], "tests/expected/size_100c.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn mtime_minus_7() -> TestResult {
+    run_h(
+        &["--mtime", "-7", "-t", "f"],
+        "tests/expected/mtime_minus_7.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mtime_plus_20() -> TestResult {
+    run_h(&["--mtime", "+20"], "tests/expected/mtime_plus_20.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn mtime_10() -> TestResult {
+    run_h(&["--mtime", "10"], "tests/expected/mtime_10.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn newer() -> TestResult {
+    run_h(
+        &["--newer", "h/big.bin", "-t", "f"],
+        "tests/expected/newer_big.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn empty() -> TestResult {
+    run_h(&["--empty"], "tests/expected/empty.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_dir() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("none"))?;
+    fs::create_dir(dir.path().join("some"))?;
+    fs::write(dir.path().join("some").join("file"), "text")?;
+
+    let root = dir.path().to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args([root, "--empty"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", dir.path().join("none").display()));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn perm_644() -> TestResult {
+    run_h(&["--perm", "644", "-t", "f"], "tests/expected/perm_644.txt")
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn perm_all_111() -> TestResult {
+    run_h(
+        &["--perm", "-111", "-t", "f"],
+        "tests/expected/perm_all_111.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn perm_any_011() -> TestResult {
+    run_h(
+        &["--perm", "/011", "-t", "f"],
+        "tests/expected/perm_any_011.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn user_and_group() -> TestResult {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(tempfile::tempdir()?.path())?;
+    let (uid, gid) = (metadata.uid().to_string(), metadata.gid().to_string());
+    run_h(
+        &["--user", &uid, "--group", &gid, "-t", "f"],
+        "tests/expected/user.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_size() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--size", "10X"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --size \"10X\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_mtime() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--mtime", "-7d"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --mtime \"-7d\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_newer() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error [23][)]", &bad);
+    Command::cargo_bin(PRG)?
+        .args(["--newer", &bad])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_user() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--user", &bad])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Invalid --user \"{}\"",
+            bad
+        )));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn prune_b() -> TestResult {
+    run(
+        &["tests/inputs", "--prune", "b"],
+        "tests/expected/prune_b.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn prune_class_type_d() -> TestResult {
+    run(
+        &["tests/inputs", "--prune", "[de]", "-t", "d"],
+        "tests/expected/prune_class_type_d.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_depth_1() -> TestResult {
+    run(
+        &["tests/inputs", "--max-depth", "1"],
+        "tests/expected/max_depth_1.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn depth_2() -> TestResult {
+    run(
+        &["tests/inputs", "--min-depth", "2", "--max-depth", "2"],
+        "tests/expected/depth_2.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn min_depth_3_prune_b() -> TestResult {
+    run(
+        &["tests/inputs", "--min-depth", "3", "--prune", "b"],
+        "tests/expected/min_depth_3_prune_b.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_depth() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--max-depth", "x"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --max-depth \"x\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_prune() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--prune", "a[b"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --prune \"a[b\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_or() -> TestResult {
+    run(
+        &["tests/inputs", "-name", "*.mp3", "-o", "-name", "*.tsv"],
+        "tests/expected/expr_or.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_not() -> TestResult {
+    run(
+        &["tests/inputs", "-name", "*.csv", "!", "-name", "b*"],
+        "tests/expected/expr_not.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_precedence() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-type",
+            "d",
+            "-name",
+            "[ab]",
+            "-o",
+            "-name",
+            "*.mp3",
+        ],
+        "tests/expected/expr_precedence.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_parens() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-type",
+            "d",
+            "(",
+            "-name",
+            "a",
+            "-or",
+            "-name",
+            "d",
+            ")",
+        ],
+        "tests/expected/expr_parens.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_with_options() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f", "-name", "*.txt"],
+        "tests/expected/expr_with_options.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_expr() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-name", "a", "-o", "-type", "x"])
+        .assert()
+        .failure()
+        .stderr(concat!(
+            "Invalid expression: invalid argument \"x\" to -type\n",
+            "    -name a -o -type x\n",
+            "                     ^\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unbalanced_expr() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "(", "-name", "a", "-o"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid expression: expected an expression",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn dies_non_utf8_expr() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let bad = OsStr::from_bytes(b"\xff");
+    Command::cargo_bin(PRG)?
+        .arg("tests/inputs")
+        .arg("-name")
+        .arg(bad)
+        .assert()
+        .failure()
+        .stderr("Invalid expression: argument \"\u{fffd}\" isn't valid UTF-8\n");
+
+    // A path that isn't valid UTF-8 is no expression.
+    Command::cargo_bin(PRG)?
+        .args([OsStr::new("tests/inputs/a/b/c"), bad])
+        .assert()
+        .stdout("tests/inputs/a/b/c\ntests/inputs/a/b/c/c.mp3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn iname_csv() -> TestResult {
+    run(
+        &["tests/inputs", "-iname", "*.CSV"],
+        "tests/expected/iname_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn name_partial() -> TestResult {
+    run(
+        &["tests/inputs", "-name", "*.cs"],
+        "tests/expected/name_partial.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn path_d_t() -> TestResult {
+    run(
+        &["tests/inputs", "-path", "*/d/*.t*"],
+        "tests/expected/path_d_t.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn regex_csv() -> TestResult {
+    run(
+        &["tests/inputs", "-regex", r".*/[ab]\.csv"],
+        "tests/expected/regex_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn iregex_wholename() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-iregex",
+            r".*/[AB]\.CSV",
+            "-o",
+            "-wholename",
+            "tests/inputs/f",
+        ],
+        "tests/expected/iregex_wholename.txt",
+    )
+}
+
+// --------------------------------------------------
+fn set_up_repo() -> Result<tempfile::TempDir, Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for sub in [".git/info", "src/sub", "target", "docs"] {
+        fs::create_dir_all(root.join(sub))?;
+    }
+    for (file, contents) in [
+        (".gitignore", "target/\n*.log\n!keep.log\n"),
+        (".git/info/exclude", "secret.txt\n"),
+        ("docs/.ignore", "*.md\n"),
+        ("src/.gitignore", "!sub.log\n"),
+    ] {
+        fs::write(root.join(file), contents)?;
+    }
+    for file in [
+        "a.log",
+        "keep.log",
+        "secret.txt",
+        ".env",
+        "src/main.rs",
+        "src/sub.log",
+        "src/sub/x.log",
+        "target/out",
+        "docs/a.md",
+        "docs/b.txt",
+    ] {
+        fs::write(root.join(file), "")?;
+    }
+    Ok(dir)
+}
+
+// --------------------------------------------------
+fn run_repo(args: &[&str], expected: &[&str]) -> TestResult {
+    let dir = set_up_repo()?;
+    let output = Command::cargo_bin(PRG)?
+        .current_dir(dir.path())
+        .args(args)
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut lines: Vec<_> = stdout.lines().map(|line| line.replace('\\', "/")).collect();
+    lines.sort();
+    assert_eq!(lines, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn gitignore() -> TestResult {
+    run_repo(
+        &["--gitignore", "-t", "f"],
+        &[
+            "./docs/b.txt",
+            "./keep.log",
+            "./src/main.rs",
+            "./src/sub.log",
+        ],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn gitignore_hidden() -> TestResult {
+    run_repo(
+        &["--gitignore", "--hidden", "-t", "f", "-name", ".*"],
+        &[
+            "./.env",
+            "./.gitignore",
+            "./docs/.ignore",
+            "./src/.gitignore",
+        ],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn gitignore_nested_path() -> TestResult {
+    run_repo(
+        &["--gitignore", "src"],
+        &["src", "src/main.rs", "src/sub", "src/sub.log"],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn gitignore_off_by_default() -> TestResult {
+    run_repo(
+        &["-name", "*.log"],
+        &["./a.log", "./keep.log", "./src/sub.log", "./src/sub/x.log"],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_hidden_without_gitignore() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("--hidden")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--gitignore"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn printf_relative() -> TestResult {
+    run(
+        &["tests/inputs/a", "-printf", "%P|%f|%h|%d|%y\\n"],
+        "tests/expected/printf_relative.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn printf_mode_size() -> TestResult {
+    let dir = set_up_h()?;
+    let output = Command::cargo_bin(PRG)?
+        .current_dir(&dir)
+        .args(["h", "-type", "f", "-printf", "%f %s %m %M\\n"])
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+
+    assert_eq!(
+        lines,
+        [
+            "big.bin 3000 755 -rwxr-xr-x",
+            "empty 0 644 -rw-r--r--",
+            "new.log 10 644 -rw-r--r--",
+            "old.log 100 600 -rw-------",
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn print0() -> TestResult {
+    let expected = Path::new("tests/inputs/a").join("b").join("b.csv");
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/a", "-name", "*.csv", "-print0"])
+        .assert()
+        .success()
+        .stdout(format!("{}\0", expected.display()));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn print_only_where_reached() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-name",
+            "*.csv",
+            "-print",
+            "-o",
+            "-name",
+            "d",
+        ],
+        "tests/expected/name_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec_each() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args([
+            "tests/inputs/d",
+            "-name",
+            "*.t*",
+            "-exec",
+            "echo",
+            "[{}]",
+            ";",
+        ])
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+
+    assert_eq!(lines, ["[tests/inputs/d/d.tsv]", "[tests/inputs/d/d.txt]"]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec_batched() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args([
+            "tests/inputs/d",
+            "-name",
+            "*.t*",
+            "-exec",
+            "echo",
+            "{}",
+            "+",
+        ])
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut paths: Vec<&str> = stdout.split_whitespace().collect();
+    paths.sort();
+
+    assert_eq!(stdout.lines().count(), 1);
+    assert_eq!(paths, ["tests/inputs/d/d.tsv", "tests/inputs/d/d.txt"]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec_as_test() -> TestResult {
+    run_h(
+        &["-type", "f", "-exec", "grep", "-q", ".", "{}", ";", "-print"],
+        "tests/expected/exec_as_test.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn exec_batched_fails() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/a", "-type", "f", "-exec", "false", "{}", "+"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn delete() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("keep"))?;
+    fs::create_dir_all(root.join("gone").join("sub"))?;
+    fs::write(root.join("keep").join("a.txt"), "")?;
+    fs::write(root.join("keep").join("b.tmp"), "")?;
+    fs::write(root.join("gone").join("sub").join("c.tmp"), "")?;
+
+    Command::cargo_bin(PRG)?
+        .arg(root)
+        .args([
+            "(", "-name", "*.tmp", "-o", "-path", "*gone*", ")", "-delete",
+        ])
+        .assert()
+        .success()
+        .stdout("");
+
+    assert!(root.join("keep").join("a.txt").exists());
+    assert!(!root.join("keep").join("b.tmp").exists());
+    assert!(!root.join("gone").exists());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn delete_fails() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let full = dir.path().join("full");
+    fs::create_dir(&full)?;
+    fs::write(full.join("a.txt"), "")?;
+
+    Command::cargo_bin(PRG)?
+        .arg(dir.path())
+        .args(["-type", "d", "-name", "full", "-delete"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("full: "));
+
+    assert!(full.join("a.txt").exists());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_exec_unterminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-exec", "echo", "{}"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid expression: missing \";\" or \"{} +\" after -exec",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_printf() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-printf", "%z"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid expression: invalid argument \"%z\" to -printf",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_match_prints_nothing() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "tests/inputs/a", "-name", "nothing"])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn non_utf8_path() -> TestResult {
+    use std::{
+        ffi::OsStr,
+        os::unix::ffi::{OsStrExt, OsStringExt},
+    };
+
+    let dir = tempfile::tempdir()?;
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    fs::write(dir.path().join(name), "")?;
+
+    let mut expected = dir.path().join(name).into_os_string().into_vec();
+    expected.push(b'\n');
+    Command::cargo_bin(PRG)?
+        .arg(dir.path())
+        .args(["-type", "f"])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn non_utf8_root() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = tempfile::tempdir()?;
+    let root = OsStr::from_bytes(b"r\xffdir");
+    fs::create_dir(dir.path().join(root))?;
+    fs::write(dir.path().join(root).join("a.txt"), "")?;
+
+    for jobs in ["1", "2"] {
+        Command::cargo_bin(PRG)?
+            .current_dir(dir.path())
+            .arg(root)
+            .args(["-j", jobs, "-type", "f", "-printf", "%H|%P\n"])
+            .assert()
+            .success()
+            .stdout(&b"r\xffdir|a.txt\n"[..]);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+fn run_sorted(args: &[&str], expected_file: &str) -> TestResult {
+    let file = format_file_name(expected_file);
+    let expected = fs::read_to_string(file.as_ref())?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sort() -> TestResult {
+    run_sorted(&["tests/inputs", "--sort"], "tests/expected/sort.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_sort() -> TestResult {
+    run_sorted(
+        &["tests/inputs", "-j", "4", "--sort"],
+        "tests/expected/sort.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_expr() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "--jobs",
+            "3",
+            "-name",
+            "*.csv",
+            "-o",
+            "-name",
+            "*.mp3",
+        ],
+        "tests/expected/name_csv_mp3.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_depth_prune() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-j",
+            "2",
+            "--min-depth",
+            "3",
+            "--prune",
+            "b",
+        ],
+        "tests/expected/min_depth_3_prune_b.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_gitignore() -> TestResult {
+    run_repo(
+        &["--gitignore", "-j", "4", "-t", "f"],
+        &[
+            "./docs/b.txt",
+            "./keep.log",
+            "./src/main.rs",
+            "./src/sub.log",
+        ],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-j", "0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --jobs \"0\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+fn run_links(args: &[&str], expected: &[&str], warning: &str) -> TestResult {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("top").join("sub"))?;
+    fs::create_dir_all(root.join("other"))?;
+    fs::write(root.join("other").join("o.txt"), "")?;
+    fs::write(root.join("top").join("sub").join("s.txt"), "")?;
+    symlink("../other", root.join("top").join("to_other"))?;
+    symlink("..", root.join("top").join("sub").join("loop"))?;
+    symlink("missing", root.join("top").join("broken"))?;
+    symlink("top", root.join("link"))?;
+
+    for jobs in ["1", "3"] {
+        let output = Command::cargo_bin(PRG)?
+            .current_dir(root)
+            .args(["-j", jobs])
+            .args(args)
+            .output()?;
+        // A loop is reported, walked past and makes the walk fail.
+        assert_eq!(output.status.success(), warning.is_empty());
+
+        let stdout = String::from_utf8(output.stdout)?;
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        lines.sort();
+        assert_eq!(lines, expected);
+
+        let stderr = String::from_utf8(output.stderr)?;
+        match warning {
+            "" => assert_eq!(stderr, ""),
+            _ => assert!(stderr.contains(warning)),
+        }
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn links_physical() -> TestResult {
+    run_links(
+        &["-P", "link", "top", "-type", "l"],
+        &["link", "top/broken", "top/sub/loop", "top/to_other"],
+        "",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn links_command_line() -> TestResult {
+    run_links(&["-H", "link", "-type", "d"], &["link", "link/sub"], "")
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn links_logical() -> TestResult {
+    run_links(
+        &["-L", "link", "-name", "*.txt", "-o", "-type", "l"],
+        &["link/broken", "link/sub/s.txt", "link/to_other/o.txt"],
+        "File system loop found: link/sub/loop points to an ancestor link",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn xdev() -> TestResult {
+    run(&["tests/inputs", "--xdev"], "tests/expected/sort.txt")?;
+    run(&["tests/inputs", "-xdev"], "tests/expected/sort.txt")
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn xdev_stays_on_root_filesystem() -> TestResult {
+    use std::os::unix::fs::MetadataExt;
+
+    let root = fs::metadata("/")?.dev();
+    for xdev in ["--xdev", "-xdev"] {
+        for jobs in ["1", "3"] {
+            let output = Command::cargo_bin(PRG)?
+                .args(["-j", jobs, "--max-depth", "2", "/", xdev])
+                .output()?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+
+            // Mount points are listed, but not walked into.
+            for mount in ["/proc", "/dev"] {
+                if fs::metadata(mount)?.dev() == root {
+                    continue;
+                }
+                let inside = format!("{}/", mount);
+                assert!(stdout.lines().any(|line| line == mount));
+                assert!(!stdout.lines().any(|line| line.starts_with(&inside)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn xtype_broken() -> TestResult {
+    run_links(&["-P", "top", "-xtype", "l"], &["top/broken"], "")
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn xtype_dir() -> TestResult {
+    run_links(
+        &["link", "top", "-xtype", "d", "-type", "l"],
+        &["link", "top/sub/loop", "top/to_other"],
+        "",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn xtype_logical() -> TestResult {
+    run_links(
+        &["-L", "top", "-xtype", "l", "-type", "d"],
+        &["top/to_other"],
+        "File system loop found",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn printf_target_type() -> TestResult {
+    run_links(
+        &["top", "-type", "l", "-printf", "%p %y%Y\n"],
+        &["top/broken lN", "top/sub/loop ld", "top/to_other ld"],
+        "",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn special_types() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let status = std::process::Command::new("mkfifo")
+        .arg(dir.path().join("fifo"))
+        .status()?;
+    assert!(status.success());
+    let _socket = std::os::unix::net::UnixListener::bind(dir.path().join("socket"))?;
+
+    for (entry_type, expected) in [("p", "fifo"), ("s", "socket")] {
+        let output = Command::cargo_bin(PRG)?
+            .current_dir(dir.path())
+            .args([".", "-type", entry_type])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            format!("./{}\n", expected)
+        );
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["/dev/null", "-type", "c", "-printf", "%y\n"])
+        .assert()
+        .success()
+        .stdout("c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_xtype() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-xtype", "x"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid argument \"x\" to -xtype"));
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use crate::{format_count, line_key, MyResult};
+use serde::Serialize;
+use std::{borrow::Cow, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Tsv,
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    line: Cow<'a, str>,
+    count: usize,
+}
+
+/// Writes one `(line, count)` record per output line in the chosen format.
+/// JSON is written as one object per line; CSV and TSV start with a header.
+pub struct RecordWriter {
+    format: Format,
+    wrote_header: bool,
+}
+
+impl RecordWriter {
+    pub fn new(format: Format) -> Self {
+        RecordWriter {
+            format,
+            wrote_header: false,
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        out: &mut impl Write,
+        count: usize,
+        line: &[u8],
+        show_count: bool,
+    ) -> MyResult<()> {
+        let record = Record {
+            line: String::from_utf8_lossy(line_key(line, false, false)),
+            count,
+        };
+
+        let delimiter = match self.format {
+            Format::Text => {
+                out.write_all(format_count(count, show_count).as_bytes())?;
+                out.write_all(line)?;
+                return Ok(());
+            }
+            Format::Json => {
+                serde_json::to_writer(&mut *out, &record)?;
+                out.write_all(b"\n")?;
+                return Ok(());
+            }
+            Format::Csv => ',',
+            Format::Tsv => '\t',
+        };
+
+        if !self.wrote_header {
+            writeln!(out, "line{}count", delimiter)?;
+            self.wrote_header = true;
+        }
+        writeln!(
+            out,
+            "{}{}{}",
+            quote(&record.line, delimiter),
+            delimiter,
+            record.count
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Quotes a field the way RFC 4180 does when it contains the delimiter, a
+/// quote or a line break.
+fn quote(field: &str, delimiter: char) -> Cow<'_, str> {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
@@ -0,0 +1,377 @@
+use crate::MyResult;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BinaryHeap, HashSet},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+};
+
+/// Rough per-digest cost of the in-memory set, including hash table overhead.
+const BYTES_PER_DIGEST: usize = 32;
+const BLOOM_HASHES: u64 = 7;
+/// Number of files spilled lines are split into by digest, and the digest
+/// bits each split uses.
+const PARTITIONS: usize = 16;
+const PARTITION_BITS: u32 = 4;
+/// A partition still too big at this depth is deduplicated in memory anyway.
+const MAX_LEVEL: u32 = 128 / PARTITION_BITS - 1;
+/// The length written for a record that only marks a digest as seen.
+const SEEN: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Spill,
+    Bloom,
+}
+
+/// 128-bit digest of a line, built from two independently seeded hashes.
+pub fn digest(line: &[u8]) -> u128 {
+    let half = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        line.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    (u128::from(half(0)) << 64) | u128::from(half(1))
+}
+
+/// Writes the first occurrence of each line, keeping memory use under
+/// `max_memory` by spilling to disk or switching to a Bloom filter (which
+/// may drop a few unique lines as false positives).
+///
+/// Spilled lines are split into files by digest and only written at the
+/// end, when each file is deduplicated in memory on its own and the
+/// survivors are merged back into input order.
+#[derive(Debug)]
+pub struct SeenLines {
+    memory: HashSet<u128>,
+    capacity: Option<usize>,
+    overflow: Overflow,
+    spill: Option<Partitions>,
+    bloom: Option<BloomFilter>,
+    seq: u64,
+}
+
+impl SeenLines {
+    pub fn new(max_memory: Option<usize>, overflow: Overflow) -> Self {
+        SeenLines {
+            memory: HashSet::new(),
+            capacity: max_memory.map(|bytes| (bytes / BYTES_PER_DIGEST).max(1)),
+            overflow,
+            spill: None,
+            bloom: None,
+            seq: 0,
+        }
+    }
+
+    /// Writes `line` unless a line with the same `key` came before it.
+    pub fn write_first(&mut self, line: &[u8], key: &[u8], write: &mut impl Write) -> MyResult<()> {
+        let digest = digest(key);
+
+        if let Some(spill) = &mut self.spill {
+            self.seq += 1;
+            return spill.push(&Record {
+                digest,
+                seq: self.seq,
+                line: Some(line.to_vec()),
+            });
+        }
+
+        let first = match &mut self.bloom {
+            Some(bloom) => bloom.insert(digest),
+            None => self.memory.insert(digest),
+        };
+        if first {
+            write.write_all(line)?;
+        }
+
+        if self
+            .capacity
+            .is_some_and(|capacity| self.memory.len() >= capacity)
+        {
+            match self.overflow {
+                Overflow::Spill => self.start_spill()?,
+                Overflow::Bloom => self.switch_to_bloom(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the first occurrences among the spilled lines.
+    pub fn finish(self, write: &mut impl Write) -> MyResult<()> {
+        match (self.spill, self.capacity) {
+            (Some(spill), Some(capacity)) => merge(spill.dedup(capacity)?, |record| {
+                Ok(write.write_all(&record.line.unwrap_or_default())?)
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn start_spill(&mut self) -> MyResult<()> {
+        let mut spill = Partitions::new(0);
+        for digest in self.memory.drain() {
+            spill.push(&Record {
+                digest,
+                seq: 0,
+                line: None,
+            })?;
+        }
+        self.memory.shrink_to_fit();
+        self.spill = Some(spill);
+        Ok(())
+    }
+
+    fn switch_to_bloom(&mut self) {
+        let bytes = self.capacity.unwrap_or(1) * BYTES_PER_DIGEST;
+        let mut bloom = BloomFilter::new(bytes * 8);
+        for digest in self.memory.drain() {
+            bloom.insert(digest);
+        }
+        self.memory.shrink_to_fit();
+        self.bloom = Some(bloom);
+    }
+}
+
+/// A spilled line in input order, or with no line, a digest already
+/// written before spilling began.
+#[derive(Debug)]
+struct Record {
+    digest: u128,
+    seq: u64,
+    line: Option<Vec<u8>>,
+}
+
+impl Record {
+    fn write(&self, writer: &mut impl Write) -> MyResult<()> {
+        writer.write_all(&self.digest.to_be_bytes())?;
+        writer.write_all(&self.seq.to_be_bytes())?;
+        match &self.line {
+            Some(line) => {
+                writer.write_all(&(line.len() as u64).to_be_bytes())?;
+                writer.write_all(line)?;
+            }
+            None => writer.write_all(&SEEN.to_be_bytes())?,
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl BufRead) -> MyResult<Option<Record>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut header = [0; 32];
+        reader.read_exact(&mut header)?;
+        let digest = u128::from_be_bytes(header[..16].try_into()?);
+        let seq = u64::from_be_bytes(header[16..24].try_into()?);
+        let line = match u64::from_be_bytes(header[24..].try_into()?) {
+            SEEN => None,
+            len => {
+                let mut line = vec![0; len as usize];
+                reader.read_exact(&mut line)?;
+                Some(line)
+            }
+        };
+
+        Ok(Some(Record { digest, seq, line }))
+    }
+}
+
+/// Records split into files by the digest bits `level` selects, each file
+/// created on its first record.
+#[derive(Debug)]
+struct Partitions {
+    files: Vec<Option<BufWriter<File>>>,
+    level: u32,
+}
+
+impl Partitions {
+    fn new(level: u32) -> Self {
+        let files = (0..PARTITIONS).map(|_| None).collect();
+        Partitions { files, level }
+    }
+
+    fn push(&mut self, record: &Record) -> MyResult<()> {
+        let i = (record.digest >> (self.level * PARTITION_BITS)) as usize % PARTITIONS;
+        let file = match &mut self.files[i] {
+            Some(file) => file,
+            file => file.insert(BufWriter::new(tempfile::tempfile()?)),
+        };
+        record.write(file)
+    }
+
+    /// Keeps the first record of each digest not marked as seen, returning
+    /// at most one file per partition, each in input order. A file with
+    /// more distinct digests than `capacity` is split again on the next
+    /// bits and its survivors merged back into one file, so no more than
+    /// a few files per level are open at once.
+    fn dedup(self, capacity: usize) -> MyResult<Vec<File>> {
+        let mut survivors = vec![];
+
+        for writer in self.files.into_iter().flatten() {
+            let mut reader = BufReader::new(rewind(writer)?);
+            let mut seen = HashSet::new();
+            let mut kept = BufWriter::new(tempfile::tempfile()?);
+            let mut too_big = false;
+
+            while let Some(record) = Record::read(&mut reader)? {
+                if seen.insert(record.digest) && record.line.is_some() {
+                    record.write(&mut kept)?;
+                }
+                if seen.len() > capacity && self.level < MAX_LEVEL {
+                    too_big = true;
+                    break;
+                }
+            }
+
+            if too_big {
+                drop(seen);
+                let mut file = reader.into_inner();
+                file.seek(SeekFrom::Start(0))?;
+                let mut reader = BufReader::new(file);
+                let mut split = Partitions::new(self.level + 1);
+                while let Some(record) = Record::read(&mut reader)? {
+                    split.push(&record)?;
+                }
+                drop(reader);
+                let mut merged = BufWriter::new(tempfile::tempfile()?);
+                merge(split.dedup(capacity)?, |record| record.write(&mut merged))?;
+                survivors.push(rewind(merged)?);
+            } else {
+                survivors.push(rewind(kept)?);
+            }
+        }
+
+        Ok(survivors)
+    }
+}
+
+/// Passes the records of `files`, each in input order, to `emit` in input
+/// order.
+fn merge(files: Vec<File>, mut emit: impl FnMut(Record) -> MyResult<()>) -> MyResult<()> {
+    let mut readers: Vec<_> = files.into_iter().map(BufReader::new).collect();
+    let mut heads = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let head = Record::read(reader)?;
+        if let Some(record) = &head {
+            heap.push(Reverse((record.seq, i)));
+        }
+        heads.push(head);
+    }
+
+    while let Some(Reverse((_, i))) = heap.pop() {
+        if let Some(record) = heads[i].take() {
+            emit(record)?;
+        }
+        heads[i] = Record::read(&mut readers[i])?;
+        if let Some(record) = &heads[i] {
+            heap.push(Reverse((record.seq, i)));
+        }
+    }
+
+    Ok(())
+}
+
+fn rewind(writer: BufWriter<File>) -> MyResult<File> {
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+#[derive(Debug)]
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+}
+
+impl BloomFilter {
+    fn new(num_bits: usize) -> Self {
+        let words = num_bits.div_ceil(64).max(1);
+        BloomFilter {
+            bits: vec![0; words],
+            num_bits: words as u64 * 64,
+        }
+    }
+
+    /// Sets the bits for `digest`, returning whether any of them was unset.
+    fn insert(&mut self, digest: u128) -> bool {
+        let h1 = digest as u64;
+        let h2 = (digest >> 64) as u64 | 1;
+        let mut added = false;
+
+        for i in 0..BLOOM_HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            if self.bits[word] & mask == 0 {
+                self.bits[word] |= mask;
+                added = true;
+            }
+        }
+
+        added
+    }
+}
+
+/// Parses a size such as `4096`, `512K`, `64M` or `1G`.
+pub fn parse_size(val: &str) -> MyResult<usize> {
+    let val = val.trim();
+    let (digits, multiplier) = match val.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&val[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&val[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&val[..i], 1 << 30),
+        _ => (val, 1),
+    };
+
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("Invalid size \"{}\"", val).into()),
+        _ => Err(format!("Invalid size \"{}\"", val).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_size, Overflow, SeenLines};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("10").unwrap(), 10);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("1m").unwrap(), 1 << 20);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("foo").is_err());
+    }
+
+    fn write_first(seen: &mut SeenLines, lines: impl Iterator<Item = String>) -> String {
+        let mut out = vec![];
+        for line in lines {
+            let line = format!("{}\n", line);
+            seen.write_first(line.as_bytes(), line.as_bytes(), &mut out)
+                .unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_spill_is_exact() {
+        let mut seen = SeenLines::new(Some(1024), Overflow::Spill);
+        let lines = (0..3000).map(|i| ((i * 7) % 1000).to_string());
+        let mut out = write_first(&mut seen, lines).into_bytes();
+        seen.finish(&mut out).unwrap();
+
+        let expected: String = (0..1000).map(|i| format!("{}\n", (i * 7) % 1000)).collect();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_bloom_remembers() {
+        let mut seen = SeenLines::new(Some(4096), Overflow::Bloom);
+        write_first(&mut seen, (0..500).map(|i| i.to_string()));
+        assert_eq!(write_first(&mut seen, (0..500).map(|i| i.to_string())), "");
+    }
+}
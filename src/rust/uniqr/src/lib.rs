@@ -1,15 +1,47 @@
+use crate::{
+    format::{Format, RecordWriter},
+    global::{Overflow, SeenLines},
+};
 use clap::{App, Arg};
+use same_file::Handle;
 use std::{
+    cmp::Reverse,
+    collections::HashMap,
     error::Error,
-    fs::File,
+    fs::{self, File},
     io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
 };
+use tempfile::NamedTempFile;
+
+mod format;
+mod global;
+
+/// Where blank lines go around the groups printed by `--all-repeated` and
+/// `--group`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimit {
+    None,
+    Prepend,
+    Append,
+    Separate,
+    Both,
+}
 
 #[derive(Debug)]
 pub struct Config {
     in_file: String,
     out_file: Option<String>,
     count: bool,
+    global: bool,
+    max_memory: Option<usize>,
+    overflow: Overflow,
+    all_repeated: Option<Delimit>,
+    group: Option<Delimit>,
+    ignore_trailing_space: bool,
+    strip_trailing_cr: bool,
+    format: Format,
+    top: Option<usize>,
 }
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
@@ -42,57 +74,328 @@ pub fn get_args() -> MyResult<Config> {
                 .long("count")
                 .short("c"),
         )
+        .arg(
+            Arg::with_name("ignore_trailing_space")
+                .takes_value(false)
+                .help("Ignore trailing whitespace when comparing lines")
+                .long("ignore-trailing-space")
+                .short("Z"),
+        )
+        .arg(
+            Arg::with_name("strip_trailing_cr")
+                .takes_value(false)
+                .help("Ignore a carriage return before each newline when comparing lines")
+                .long("strip-trailing-cr"),
+        )
+        .arg(
+            Arg::with_name("global")
+                .takes_value(false)
+                .help("Remove all repeated lines, not just adjacent ones, keeping the first occurrence")
+                .conflicts_with("count")
+                .long("global")
+                .short("g"),
+        )
+        .arg(
+            Arg::with_name("max_memory")
+                .value_name("SIZE")
+                .help("Memory budget for --global, e.g. 512K, 64M, 1G")
+                .requires("global")
+                .long("max-memory"),
+        )
+        .arg(
+            Arg::with_name("overflow")
+                .value_name("MODE")
+                .help("What --global does once --max-memory is reached [default: spill]")
+                .possible_values(&["spill", "bloom"])
+                .requires("global")
+                .long("overflow"),
+        )
+        .arg(
+            Arg::with_name("all_repeated")
+                .value_name("METHOD")
+                .help("Print all duplicate lines, delimiting groups with blank lines as METHOD says")
+                .possible_values(&["none", "prepend", "separate"])
+                .min_values(0)
+                .require_equals(true)
+                .conflicts_with_all(&["count", "global"])
+                .long("all-repeated")
+                .short("D"),
+        )
+        .arg(
+            Arg::with_name("group")
+                .value_name("METHOD")
+                .help("Print all lines, delimiting groups with blank lines as METHOD says")
+                .possible_values(&["separate", "prepend", "append", "both"])
+                .min_values(0)
+                .require_equals(true)
+                .conflicts_with_all(&["count", "global", "all_repeated"])
+                .long("group"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .value_name("FORMAT")
+                .help("Output format, text by default; json prints one {line, count} object per line")
+                .possible_values(&["text", "json", "csv", "tsv"])
+                .conflicts_with_all(&["global", "all_repeated", "group"])
+                .long("format"),
+        )
+        .arg(
+            Arg::with_name("top")
+                .value_name("N")
+                .help("Print the N most frequent lines anywhere in the input, with counts")
+                .conflicts_with_all(&["global", "all_repeated", "group"])
+                .long("top"),
+        )
         .get_matches();
 
     let in_file = matches.value_of("in_file").map(String::from).unwrap();
     let out_file = matches.value_of("out_file").map(String::from);
+    let max_memory = matches
+        .value_of("max_memory")
+        .map(|val| global::parse_size(val).map_err(|_| format!("Invalid --max-memory \"{}\"", val)))
+        .transpose()?;
+    let overflow = match matches.value_of("overflow") {
+        Some("bloom") => Overflow::Bloom,
+        _ => Overflow::Spill,
+    };
+
+    let format = match matches.value_of("format") {
+        Some("json") => Format::Json,
+        Some("csv") => Format::Csv,
+        Some("tsv") => Format::Tsv,
+        _ => Format::Text,
+    };
+    let top = matches
+        .value_of("top")
+        .map(|val| match val.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("Invalid --top \"{}\"", val)),
+        })
+        .transpose()?;
+
+    let delimit = |name: &str, default: Delimit| {
+        matches
+            .is_present(name)
+            .then(|| match matches.value_of(name) {
+                Some("none") => Delimit::None,
+                Some("prepend") => Delimit::Prepend,
+                Some("append") => Delimit::Append,
+                Some("separate") => Delimit::Separate,
+                Some("both") => Delimit::Both,
+                _ => default,
+            })
+    };
 
     Ok(Config {
         in_file,
         out_file,
         count: matches.is_present("count"),
+        global: matches.is_present("global"),
+        max_memory,
+        overflow,
+        all_repeated: delimit("all_repeated", Delimit::None),
+        group: delimit("group", Delimit::Separate),
+        ignore_trailing_space: matches.is_present("ignore_trailing_space"),
+        strip_trailing_cr: matches.is_present("strip_trailing_cr"),
+        format,
+        top,
     })
 }
 
 pub fn run(config: Config) -> MyResult<()> {
-    let mut file = open(&config.in_file).map_err(|e| format!("{}:  {}", config.in_file, e))?;
-    let mut line = String::new();
-    let mut previous: String = String::new();
+    let file = open(&config.in_file).map_err(|e| format!("{}:  {}", config.in_file, e))?;
+
+    match output_replaces_input(&config)? {
+        Some(target) => {
+            // Write next to the input and rename over it only once all of the
+            // input has been read, so an error never leaves it truncated.
+            let dir = target.parent().unwrap_or_else(|| Path::new("."));
+            let mut tmp = NamedTempFile::new_in(dir)?;
+            uniq(&config, file, BufWriter::new(tmp.as_file_mut()))?;
+            fs::set_permissions(tmp.path(), fs::metadata(&target)?.permissions())?;
+            tmp.persist(&target)
+                .map_err(|e| format!("{}: {}", target.display(), e))?;
+            Ok(())
+        }
+        None => uniq(&config, file, get_writer(&config.out_file)?),
+    }
+}
+
+fn uniq(config: &Config, mut file: impl BufRead, mut write: impl Write) -> MyResult<()> {
+    let mut line: Vec<u8> = vec![];
+    let mut previous: Vec<u8> = vec![];
     let mut count: usize = 0;
 
-    let mut write = get_writer(&config.out_file)?;
+    if config.global {
+        let mut seen = SeenLines::new(config.max_memory, config.overflow);
+        while file.read_until(b'\n', &mut line)? > 0 {
+            seen.write_first(&line, config.key(&line), &mut write)?;
+            line.clear();
+        }
+        seen.finish(&mut write)?;
+        write.flush()?;
+        return Ok(());
+    }
+
+    let mut records = RecordWriter::new(config.format);
+
+    if let Some(top) = config.top {
+        let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut entries: Vec<(usize, Vec<u8>)> = vec![];
+        while file.read_until(b'\n', &mut line)? > 0 {
+            match index.get(config.key(&line)) {
+                Some(&i) => entries[i].0 += 1,
+                None => {
+                    index.insert(config.key(&line).to_vec(), entries.len());
+                    if !line.ends_with(b"\n") {
+                        line.push(b'\n');
+                    }
+                    entries.push((1, line.clone()));
+                }
+            }
+            line.clear();
+        }
+
+        // A stable sort keeps lines with equal counts in first-seen order.
+        entries.sort_by_key(|(count, _)| Reverse(*count));
+        for (count, line) in entries.iter().take(top) {
+            records.write(&mut write, *count, line, true)?;
+        }
+        write.flush()?;
+        return Ok(());
+    }
+
+    let keep_members = config.all_repeated.is_some() || config.group.is_some();
+    let mut members: Vec<Vec<u8>> = vec![];
+    let mut printed_groups = false;
 
-    let mut print_content = |count: usize, content: &String| -> MyResult<()> {
-        if count > 0 {
-            let content = format!("{}{}", format_count(count, config.count), content);
-            write.write(content.as_bytes())?;
+    let mut print_content = |count: usize, content: &[u8], members: &[Vec<u8>]| -> MyResult<()> {
+        match (config.all_repeated, config.group) {
+            (Some(delimit), _) if count > 1 => {
+                write_group(&mut write, members, delimit, printed_groups)?;
+                printed_groups = true;
+            }
+            (None, Some(delimit)) if count > 0 => {
+                write_group(&mut write, members, delimit, printed_groups)?;
+                printed_groups = true;
+            }
+            (None, None) if count > 0 => {
+                records.write(&mut write, count, content, config.count)?;
+            }
+            _ => {}
         }
 
         Ok(())
     };
 
     loop {
-        let bytes = file.read_line(&mut line)?;
+        let bytes = file.read_until(b'\n', &mut line)?;
         if bytes == 0 {
-            print_content(count, &previous)?;
+            print_content(count, &previous, &members)?;
             break;
         }
 
-        if line.trim_end() != previous.trim_end() {
-            print_content(count, &previous)?;
+        if count == 0 || config.key(&line) != config.key(&previous) {
+            print_content(count, &previous, &members)?;
             count = 0;
             previous = line.clone();
+            members.clear();
         }
 
+        if keep_members {
+            members.push(line.clone());
+        }
         count += 1;
         line.clear();
     }
 
+    if matches!(config.group, Some(Delimit::Append | Delimit::Both)) && printed_groups {
+        write.write_all(b"\n")?;
+    }
+
+    write.flush()?;
+    Ok(())
+}
+
+/// Returns the path of the output file when it is the same file as the
+/// input, resolved through any symlinks.
+fn output_replaces_input(config: &Config) -> MyResult<Option<PathBuf>> {
+    let out_file = match &config.out_file {
+        Some(out_file) if Path::new(out_file).exists() => out_file,
+        _ => return Ok(None),
+    };
+
+    let input = match config.in_file.as_str() {
+        "-" => Handle::stdin()?,
+        in_file => Handle::from_path(in_file)?,
+    };
+
+    if input == Handle::from_path(out_file)? {
+        Ok(Some(fs::canonicalize(out_file)?))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Config {
+    fn key<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        line_key(line, self.strip_trailing_cr, self.ignore_trailing_space)
+    }
+}
+
+/// Returns the part of `line` that is compared against its neighbours: the
+/// line without its `\n` ending, also without a `\r` before it when
+/// `strip_trailing_cr` is set, and without any trailing whitespace when
+/// `ignore_trailing_space` is set.
+pub fn line_key(line: &[u8], strip_trailing_cr: bool, ignore_trailing_space: bool) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = if strip_trailing_cr {
+        line.strip_suffix(b"\r").unwrap_or(line)
+    } else {
+        line
+    };
+
+    if ignore_trailing_space {
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        &line[..end]
+    } else {
+        line
+    }
+}
+
+/// Writes every member of a run, preceded by a blank line when `delimit`
+/// calls for one. A group always ends its last line, even the input's final
+/// line without a `\n`, so whatever follows starts on a line of its own.
+fn write_group(
+    write: &mut impl Write,
+    members: &[Vec<u8>],
+    delimit: Delimit,
+    printed_groups: bool,
+) -> MyResult<()> {
+    let blank = match delimit {
+        Delimit::None => false,
+        Delimit::Prepend | Delimit::Both => true,
+        Delimit::Separate | Delimit::Append => printed_groups,
+    };
+
+    if blank {
+        write.write_all(b"\n")?;
+    }
+    for member in members {
+        write.write_all(member)?;
+    }
+    if members.last().is_some_and(|last| !last.ends_with(b"\n")) {
+        write.write_all(b"\n")?;
+    }
+
     Ok(())
 }
 
 pub fn format_count(count: usize, show: bool) -> String {
-    if show == true {
+    if show {
         format!("{:>4} ", count)
     } else {
         "".to_string()
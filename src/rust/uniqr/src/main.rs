@@ -1,4 +1,3 @@
-
 fn main() {
     if let Err(e) = uniqr::get_args().and_then(uniqr::run) {
         eprint!("{}", e);
@@ -2,7 +2,7 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
 use std::fs;
-use tempfile::NamedTempFile;
+use tempfile::{tempdir, NamedTempFile};
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -121,6 +121,7 @@ fn run(test: &Test) -> TestResult {
 }
 
 // --------------------------------------------------
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn run_count(test: &Test) -> TestResult {
     let expected = fs::read_to_string(test.out_count)?;
     Command::cargo_bin(PRG)?
@@ -157,6 +158,7 @@ fn run_stdin_count(test: &Test) -> TestResult {
 }
 
 // --------------------------------------------------
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn run_outfile(test: &Test) -> TestResult {
     let expected = fs::read_to_string(test.out)?;
     let outfile = NamedTempFile::new()?;
@@ -174,6 +176,7 @@ fn run_outfile(test: &Test) -> TestResult {
 }
 
 // --------------------------------------------------
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn run_outfile_count(test: &Test) -> TestResult {
     let outfile = NamedTempFile::new()?;
     let outpath = &outfile.path().to_str().unwrap();
@@ -192,6 +195,7 @@ fn run_outfile_count(test: &Test) -> TestResult {
 }
 
 // --------------------------------------------------
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn run_stdin_outfile_count(test: &Test) -> TestResult {
     let input = fs::read_to_string(test.input)?;
     let outfile = NamedTempFile::new()?;
@@ -605,3 +609,557 @@ fn t6_outfile_count() -> TestResult {
 fn t6_stdin_outfile_count() -> TestResult {
     run_stdin_outfile_count(&T6)
 }
+
+// --------------------------------------------------
+fn run_args(args: &[&str], input: &str, expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .arg(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+fn gen_numbers(count: usize) -> String {
+    (0..count).map(|n| format!("{}\n", n % 1000)).collect()
+}
+
+#[test]
+fn dies_global_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--global", "--count", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_max_memory() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--global", "--max-memory", "lots", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --max-memory \"lots\""));
+    Ok(())
+}
+
+#[test]
+fn dies_overflow_without_global() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--overflow", "bloom", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--global"));
+    Ok(())
+}
+
+#[test]
+fn three_global() -> TestResult {
+    run_args(&["--global"], THREE.input, "tests/expected/three.txt.g.out")
+}
+
+#[test]
+fn skip_global() -> TestResult {
+    run_args(&["--global"], SKIP.input, "tests/expected/skip.txt.g.out")
+}
+
+#[test]
+fn t5_global() -> TestResult {
+    run_args(&["--global"], T5.input, "tests/expected/t5.txt.g.out")
+}
+
+#[test]
+fn t6_global() -> TestResult {
+    run_args(
+        &["--global", "--max-memory", "32"],
+        T6.input,
+        "tests/expected/t6.txt.g.out",
+    )
+}
+
+#[test]
+fn global_spill() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--global", "--max-memory", "1K", "--overflow", "spill"])
+        .write_stdin(gen_numbers(5000))
+        .assert()
+        .success()
+        .stdout(gen_numbers(1000));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn global_spill_many_distinct() -> TestResult {
+    // Every line is distinct, so the spill splits many levels deep and must
+    // only hold a few dozen files open per level.
+    let input: String = (0..100_000).map(|n| format!("{}\n", n)).collect();
+    Command::new("sh")
+        .args(["-c", "ulimit -n 128 && exec \"$0\" --global --max-memory 1K"])
+        .arg(env!("CARGO_BIN_EXE_uniqr"))
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(input);
+    Ok(())
+}
+
+#[test]
+fn global_bloom() -> TestResult {
+    let out = Command::cargo_bin(PRG)?
+        .args(["--global", "--max-memory", "64K", "--overflow", "bloom"])
+        .write_stdin(gen_numbers(5000))
+        .output()?;
+    assert!(out.status.success());
+
+    // False positives may drop unique lines, but never let a repeat through.
+    let stdout = String::from_utf8(out.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    let mut unique = lines.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(lines.len(), unique.len());
+    assert!(lines.len() <= 1000);
+    Ok(())
+}
+
+#[test]
+fn dies_all_repeated_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-D", "-c", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_group_all_repeated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--group", "-D", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn three_all_repeated() -> TestResult {
+    run_args(&["-D"], THREE.input, "tests/expected/three.txt.D.out")
+}
+
+#[test]
+fn three_all_repeated_separate() -> TestResult {
+    run_args(
+        &["--all-repeated=separate"],
+        THREE.input,
+        "tests/expected/three.txt.D.separate.out",
+    )
+}
+
+#[test]
+fn three_group() -> TestResult {
+    run_args(
+        &["--group"],
+        THREE.input,
+        "tests/expected/three.txt.group.out",
+    )
+}
+
+#[test]
+fn three_group_both() -> TestResult {
+    run_args(
+        &["--group=both"],
+        THREE.input,
+        "tests/expected/three.txt.group.both.out",
+    )
+}
+
+#[test]
+fn skip_all_repeated() -> TestResult {
+    run_args(&["-D"], SKIP.input, "tests/expected/skip.txt.D.out")
+}
+
+#[test]
+fn skip_all_repeated_separate() -> TestResult {
+    run_args(
+        &["--all-repeated=separate"],
+        SKIP.input,
+        "tests/expected/skip.txt.D.separate.out",
+    )
+}
+
+#[test]
+fn skip_group() -> TestResult {
+    run_args(
+        &["--group"],
+        SKIP.input,
+        "tests/expected/skip.txt.group.out",
+    )
+}
+
+#[test]
+fn skip_group_both() -> TestResult {
+    run_args(
+        &["--group=both"],
+        SKIP.input,
+        "tests/expected/skip.txt.group.both.out",
+    )
+}
+
+#[test]
+fn t5_all_repeated() -> TestResult {
+    run_args(&["-D"], T5.input, "tests/expected/t5.txt.D.out")
+}
+
+#[test]
+fn t5_all_repeated_separate() -> TestResult {
+    run_args(
+        &["--all-repeated=separate"],
+        T5.input,
+        "tests/expected/t5.txt.D.separate.out",
+    )
+}
+
+#[test]
+fn t5_group() -> TestResult {
+    run_args(&["--group"], T5.input, "tests/expected/t5.txt.group.out")
+}
+
+#[test]
+fn t5_group_both() -> TestResult {
+    run_args(
+        &["--group=both"],
+        T5.input,
+        "tests/expected/t5.txt.group.both.out",
+    )
+}
+
+#[test]
+fn t6_all_repeated() -> TestResult {
+    run_args(&["-D"], T6.input, "tests/expected/t6.txt.D.out")
+}
+
+#[test]
+fn t6_all_repeated_separate() -> TestResult {
+    run_args(
+        &["--all-repeated=separate"],
+        T6.input,
+        "tests/expected/t6.txt.D.separate.out",
+    )
+}
+
+#[test]
+fn t6_group() -> TestResult {
+    run_args(&["--group"], T6.input, "tests/expected/t6.txt.group.out")
+}
+
+#[test]
+fn t6_group_both() -> TestResult {
+    run_args(
+        &["--group=both"],
+        T6.input,
+        "tests/expected/t6.txt.group.both.out",
+    )
+}
+
+#[test]
+fn crlf() -> TestResult {
+    run_args(&[], "tests/inputs/crlf.txt", "tests/expected/crlf.txt.out")
+}
+
+#[test]
+fn crlf_count() -> TestResult {
+    run_args(
+        &["-c"],
+        "tests/inputs/crlf.txt",
+        "tests/expected/crlf.txt.c.out",
+    )
+}
+
+#[test]
+fn crlf_strip_trailing_cr() -> TestResult {
+    run_args(
+        &["--strip-trailing-cr"],
+        "tests/inputs/crlf.txt",
+        "tests/expected/crlf.txt.cr.out",
+    )
+}
+
+#[test]
+fn crlf_strip_trailing_cr_count() -> TestResult {
+    run_args(
+        &["--strip-trailing-cr", "-c"],
+        "tests/inputs/crlf.txt",
+        "tests/expected/crlf.txt.cr.c.out",
+    )
+}
+
+#[test]
+fn spaces() -> TestResult {
+    run_args(
+        &[],
+        "tests/inputs/spaces.txt",
+        "tests/expected/spaces.txt.out",
+    )
+}
+
+#[test]
+fn spaces_count() -> TestResult {
+    run_args(
+        &["-c"],
+        "tests/inputs/spaces.txt",
+        "tests/expected/spaces.txt.c.out",
+    )
+}
+
+#[test]
+fn invalid() -> TestResult {
+    run_args(
+        &[],
+        "tests/inputs/invalid.txt",
+        "tests/expected/invalid.txt.out",
+    )
+}
+
+#[test]
+fn invalid_count() -> TestResult {
+    run_args(
+        &["-c"],
+        "tests/inputs/invalid.txt",
+        "tests/expected/invalid.txt.c.out",
+    )
+}
+
+#[test]
+fn nonl() -> TestResult {
+    run_args(&[], "tests/inputs/nonl.txt", "tests/expected/nonl.txt.out")
+}
+
+#[test]
+fn nonl_count() -> TestResult {
+    run_args(
+        &["-c"],
+        "tests/inputs/nonl.txt",
+        "tests/expected/nonl.txt.c.out",
+    )
+}
+
+#[test]
+fn nonl_group() -> TestResult {
+    run_args(
+        &["--group=separate"],
+        "tests/inputs/nonl.txt",
+        "tests/expected/nonl.txt.group.out",
+    )
+}
+
+#[test]
+fn nonl_group_append() -> TestResult {
+    run_args(
+        &["--group=append"],
+        "tests/inputs/nonl.txt",
+        "tests/expected/nonl.txt.group.append.out",
+    )
+}
+
+#[test]
+fn blank() -> TestResult {
+    run_args(
+        &[],
+        "tests/inputs/blank.txt",
+        "tests/expected/blank.txt.out",
+    )
+}
+
+#[test]
+fn blank_count() -> TestResult {
+    run_args(
+        &["-c"],
+        "tests/inputs/blank.txt",
+        "tests/expected/blank.txt.c.out",
+    )
+}
+
+#[test]
+fn spaces_ignore_trailing_space() -> TestResult {
+    run_args(
+        &["--ignore-trailing-space"],
+        "tests/inputs/spaces.txt",
+        "tests/expected/spaces.txt.Z.out",
+    )
+}
+
+#[test]
+fn dies_bad_top() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--top", "0", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --top \"0\""));
+    Ok(())
+}
+
+#[test]
+fn dies_format_group() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "--group", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn three_json() -> TestResult {
+    run_args(
+        &["--format", "json"],
+        THREE.input,
+        "tests/expected/three.txt.json.out",
+    )
+}
+
+#[test]
+fn three_csv() -> TestResult {
+    run_args(
+        &["--format", "csv"],
+        THREE.input,
+        "tests/expected/three.txt.csv.out",
+    )
+}
+
+#[test]
+fn three_tsv() -> TestResult {
+    run_args(
+        &["--format", "tsv"],
+        THREE.input,
+        "tests/expected/three.txt.tsv.out",
+    )
+}
+
+#[test]
+fn quote_json() -> TestResult {
+    run_args(
+        &["--format", "json"],
+        "tests/inputs/quote.txt",
+        "tests/expected/quote.txt.json.out",
+    )
+}
+
+#[test]
+fn quote_csv() -> TestResult {
+    run_args(
+        &["--format", "csv"],
+        "tests/inputs/quote.txt",
+        "tests/expected/quote.txt.csv.out",
+    )
+}
+
+#[test]
+fn quote_tsv() -> TestResult {
+    run_args(
+        &["--format", "tsv"],
+        "tests/inputs/quote.txt",
+        "tests/expected/quote.txt.tsv.out",
+    )
+}
+
+#[test]
+fn three_top() -> TestResult {
+    run_args(
+        &["--top", "2"],
+        THREE.input,
+        "tests/expected/three.txt.top2.out",
+    )
+}
+
+#[test]
+fn quote_top_json() -> TestResult {
+    run_args(
+        &["--top", "3", "--format", "json"],
+        "tests/inputs/quote.txt",
+        "tests/expected/quote.txt.top3.json.out",
+    )
+}
+
+// --------------------------------------------------
+fn copy_to_tempdir(test: &Test) -> Result<(tempfile::TempDir, String), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let path = dir.path().join("input.txt");
+    fs::copy(test.input, &path)?;
+    Ok((dir, path.to_str().unwrap().to_string()))
+}
+
+#[test]
+fn same_file_outfile() -> TestResult {
+    let (_dir, path) = copy_to_tempdir(&THREE)?;
+    Command::cargo_bin(PRG)?
+        .args([&path, &path, "-c"])
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(
+        fs::read_to_string(&path)?,
+        fs::read_to_string(THREE.out_count)?
+    );
+    Ok(())
+}
+
+#[test]
+fn same_file_stdin() -> TestResult {
+    let (_dir, path) = copy_to_tempdir(&THREE)?;
+    Command::cargo_bin(PRG)?
+        .args(["-", &path])
+        .pipe_stdin(&path)?
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(fs::read_to_string(&path)?, fs::read_to_string(THREE.out)?);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn same_file_symlink() -> TestResult {
+    let (dir, path) = copy_to_tempdir(&THREE)?;
+    let link = dir.path().join("link.txt");
+    std::os::unix::fs::symlink(&path, &link)?;
+    Command::cargo_bin(PRG)?
+        .args([path.as_str(), link.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+
+    assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+    assert_eq!(fs::read_to_string(&path)?, fs::read_to_string(THREE.out)?);
+    assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn same_file_failed_write() -> TestResult {
+    // Writes past the file size limit fail with EFBIG once SIGXFSZ is
+    // ignored, so the run stops partway through the output.
+    let dir = tempdir()?;
+    let path = dir.path().join("input.txt");
+    let input: String = (0..100_000).map(|n| format!("{}\n", n)).collect();
+    fs::write(&path, &input)?;
+    Command::new("sh")
+        .args(["-c", "trap '' XFSZ && ulimit -f 64 && exec \"$0\" \"$1\" \"$1\""])
+        .arg(env!("CARGO_BIN_EXE_uniqr"))
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("File too large"));
+
+    assert_eq!(fs::read_to_string(&path)?, input);
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Compares the `read_line` implementation `count` replaced, the general
+//! counting engine and the `-l` and `-c` fast paths.
+//! Run with `cargo bench -p wcr`.
+use std::{
+    fs,
+    hint::black_box,
+    io::{BufRead, Cursor},
+    time::{Duration, Instant},
+};
+
+const SIZE: usize = 64 * 1024 * 1024;
+const ROUNDS: u32 = 5;
+
+fn time(name: &str, mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{:<24}{:>10.2?}", name, elapsed);
+    elapsed
+}
+
+/// The `count` this crate started with, which reads a `String` per line.
+fn count_by_line(mut file: impl BufRead) -> (usize, usize, usize, usize) {
+    let (mut num_lines, mut num_words, mut num_bytes, mut num_chars) = (0, 0, 0, 0);
+    let mut line = String::new();
+
+    loop {
+        let bytes = file.read_line(&mut line).unwrap();
+        if bytes == 0 {
+            break;
+        }
+
+        num_lines += 1;
+        num_bytes += bytes;
+        num_chars += line.chars().count();
+        num_words += line.split_whitespace().count();
+        line.clear();
+    }
+
+    (num_lines, num_words, num_bytes, num_chars)
+}
+
+fn main() {
+    let line = "The quick brown fox jumps over the lazy dog, again and again.\n";
+    let text = line.repeat(SIZE / line.len());
+    let path = std::env::temp_dir().join(format!("wcr-bench-{}.txt", std::process::id()));
+    fs::write(&path, &text).unwrap();
+
+    println!("counting {} MiB", text.len() / 1024 / 1024);
+    let before = time("read_line (before)", || {
+        black_box(count_by_line(Cursor::new(text.as_bytes())));
+    });
+    let full = time("count (all columns)", || {
+        black_box(wcr::count(Cursor::new(text.as_bytes()), &wcr::WordRule::Posix).unwrap());
+    });
+    let lines = time("count_lines (-l)", || {
+        black_box(wcr::count_lines(Cursor::new(text.as_bytes())).unwrap());
+    });
+    let bytes = time("metadata (-c)", || {
+        black_box(fs::metadata(&path).unwrap().len());
+    });
+
+    for (name, elapsed) in [("count", full), ("-l", lines), ("-c", bytes)] {
+        println!(
+            "{} speedup: {:.1}x over read_line, {:.1}x over count",
+            name,
+            before.as_secs_f64() / elapsed.as_secs_f64(),
+            full.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+    fs::remove_file(&path).unwrap();
+}
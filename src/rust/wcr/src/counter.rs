@@ -0,0 +1,270 @@
+use crate::{
+    words::{Matcher, Segmenter, WordRule},
+    FileInfo,
+};
+use std::{
+    io::{self, BufRead},
+    str,
+};
+use unicode_width::UnicodeWidthChar;
+
+const TAB_SIZE: usize = 8;
+
+/// Incremental counter that accepts input a buffer at a time. Input does not
+/// have to be valid UTF-8: like wc, invalid bytes only count towards bytes,
+/// and a multibyte character split across two buffers is decoded once.
+///
+/// Counters for consecutive pieces of the same input can be joined with
+/// [`Counter::append`], which is how large files are counted in parallel.
+#[derive(Debug, Default)]
+pub struct Counter {
+    info: FileInfo,
+    rule: WordRule,
+    in_word: bool,
+    /// Whether the first character seen, if any, was part of a word.
+    first_is_word: Option<bool>,
+    seen_break: bool,
+    /// Width of the text before the first line break, for each column the
+    /// input could have started at modulo the tab size. The true width is
+    /// only known once the preceding input has been counted.
+    leading: [usize; TAB_SIZE],
+    /// Width of the text since the last line break.
+    line_width: usize,
+    pending: Vec<u8>,
+    /// Word counting for [`WordRule::Unicode`] and [`WordRule::Regex`].
+    segmenter: Segmenter,
+    matcher: Option<Matcher>,
+}
+
+/// Wraps counts made without decoding the text, such as by [`crate::count_lines`],
+/// so they can be appended like any other piece.
+impl From<FileInfo> for Counter {
+    fn from(info: FileInfo) -> Self {
+        Counter {
+            info,
+            ..Counter::default()
+        }
+    }
+}
+
+impl Counter {
+    pub fn new(rule: &WordRule) -> Self {
+        Counter {
+            rule: rule.clone(),
+            matcher: match rule {
+                WordRule::Regex(regex) => Some(Matcher::new(regex)),
+                _ => None,
+            },
+            ..Counter::default()
+        }
+    }
+
+    /// Counts everything left to read in `file`.
+    pub fn read_all(&mut self, mut file: impl BufRead) -> io::Result<()> {
+        loop {
+            let buf = file.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+
+            let len = buf.len();
+            self.update(buf);
+            file.consume(len);
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.info.num_bytes += buf.len();
+        if let Some(matcher) = &mut self.matcher {
+            self.info.num_words += matcher.update(buf);
+        }
+        let mut input = buf;
+
+        if !self.pending.is_empty() {
+            let take = input.len().min(4 - self.pending.len());
+            let mut joined = self.pending.clone();
+            joined.extend_from_slice(&input[..take]);
+
+            let used = match str::from_utf8(&joined) {
+                Ok(s) => self.first_char(s),
+                Err(e) if e.valid_up_to() > 0 => {
+                    self.first_char(str::from_utf8(&joined[..e.valid_up_to()]).unwrap())
+                }
+                Err(e) => match e.error_len() {
+                    Some(len) => {
+                        self.invalid();
+                        len
+                    }
+                    None => {
+                        // Still incomplete, so the whole buffer was consumed.
+                        self.pending = joined;
+                        return;
+                    }
+                },
+            };
+
+            input = &input[used.saturating_sub(self.pending.len())..];
+            self.pending.clear();
+        }
+
+        loop {
+            match str::from_utf8(input) {
+                Ok(s) => {
+                    self.chars(s);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, rest) = input.split_at(e.valid_up_to());
+                    self.chars(str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        Some(len) => {
+                            self.invalid();
+                            input = &rest[len..];
+                        }
+                        None => {
+                            self.pending.extend_from_slice(rest);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the counts of `next`, which must have counted the input that
+    /// directly follows this counter's, starting at a character boundary.
+    /// Rules other than [`WordRule::Posix`] need it to start a line.
+    pub fn append(&mut self, next: Counter) {
+        let info = &mut self.info;
+        info.num_lines += next.info.num_lines;
+        info.num_bytes += next.info.num_bytes;
+        info.num_chars += next.info.num_chars;
+        info.num_words += next.info.num_words;
+        info.max_line_length = info.max_line_length.max(next.info.max_line_length);
+
+        // A word running across the boundary was counted on both sides.
+        if self.in_word && next.first_is_word == Some(true) {
+            info.num_words -= 1;
+        }
+        if next.first_is_word.is_some() {
+            self.in_word = next.in_word;
+        }
+        self.first_is_word = self.first_is_word.or(next.first_is_word);
+
+        // No word runs across lines, so those before a line start are done.
+        if next.info.num_bytes > 0 {
+            self.segmenter = next.segmenter;
+            if next.matcher.is_some() {
+                self.matcher = next.matcher;
+            }
+        }
+
+        if self.seen_break {
+            let spanning = self.line_width + next.leading[self.line_width % TAB_SIZE];
+            if next.seen_break {
+                info.max_line_length = info.max_line_length.max(spanning);
+                self.line_width = next.line_width;
+            } else {
+                self.line_width = spanning;
+            }
+        } else {
+            for (start, width) in self.leading.iter_mut().enumerate() {
+                *width += next.leading[(start + *width) % TAB_SIZE];
+            }
+            if next.seen_break {
+                self.seen_break = true;
+                self.line_width = next.line_width;
+            }
+        }
+
+        self.pending = next.pending;
+    }
+
+    /// Returns the counts so far, as if the input ended here.
+    pub fn counts(&self) -> FileInfo {
+        let mut info = self.info.clone();
+        let mut longest = info.max_line_length.max(self.leading[0]);
+        if self.seen_break {
+            longest = longest.max(self.line_width);
+        }
+        info.max_line_length = longest;
+        info.num_words +=
+            self.segmenter.pending() + self.matcher.as_ref().map_or(0, Matcher::pending);
+        info
+    }
+
+    pub fn finish(self) -> FileInfo {
+        self.counts()
+    }
+
+    /// Counts the first character of `s`, returning its length in bytes.
+    fn first_char(&mut self, s: &str) -> usize {
+        let c = s.chars().next().unwrap();
+        self.char(c);
+        c.len_utf8()
+    }
+
+    fn chars(&mut self, s: &str) {
+        for c in s.chars() {
+            self.char(c);
+        }
+    }
+
+    /// Counts a byte sequence that isn't valid UTF-8, which separates
+    /// Unicode words like the replacement character would.
+    fn invalid(&mut self) {
+        if let WordRule::Unicode = self.rule {
+            self.info.num_words += self.segmenter.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+
+    fn char(&mut self, c: char) {
+        self.info.num_chars += 1;
+
+        match self.rule {
+            WordRule::Posix => {
+                let is_word = !c.is_whitespace();
+                if self.first_is_word.is_none() {
+                    self.first_is_word = Some(is_word);
+                }
+                if !is_word {
+                    self.in_word = false;
+                } else if !self.in_word {
+                    self.in_word = true;
+                    self.info.num_words += 1;
+                }
+            }
+            WordRule::Unicode => self.info.num_words += self.segmenter.push(c),
+            WordRule::Regex(_) => {}
+        }
+
+        // Like wc, a carriage return or form feed starts the width over.
+        match c {
+            '\n' | '\r' | '\x0c' => {
+                if c == '\n' {
+                    self.info.num_lines += 1;
+                }
+                if self.seen_break {
+                    self.info.max_line_length = self.info.max_line_length.max(self.line_width);
+                }
+                self.seen_break = true;
+                self.line_width = 0;
+            }
+            _ if self.seen_break => self.line_width = advance(self.line_width, c),
+            _ => {
+                for (start, width) in self.leading.iter_mut().enumerate() {
+                    *width = advance(start + *width, c) - start;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the column after printing `c` at column `width`: tabs advance to
+/// the next tab stop and wide characters take two columns.
+fn advance(width: usize, c: char) -> usize {
+    match c {
+        '\t' => width + TAB_SIZE - width % TAB_SIZE,
+        _ => width + c.width().unwrap_or(0),
+    }
+}
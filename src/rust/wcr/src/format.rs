@@ -0,0 +1,111 @@
+use crate::{Config, FileInfo, MyResult};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{borrow::Cow, error::Error, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+/// One file, or the total when `path` is `None`; stdin's path is `-`. Only
+/// the selected counts are included, and they are null when the file
+/// couldn't be read.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    path: Option<&'a str>,
+    #[serde(flatten)]
+    counts: Map<String, Value>,
+    error: Option<String>,
+}
+
+/// Writes one record per file in the chosen format. JSON is written as one
+/// object per line; CSV starts with a header naming the selected counts.
+pub struct RecordWriter {
+    format: Format,
+    columns: Vec<&'static str>,
+    wrote_header: bool,
+}
+
+impl RecordWriter {
+    pub fn new(format: Format, config: &Config) -> Self {
+        let columns = [
+            ("lines", config.lines),
+            ("words", config.words),
+            ("chars", config.chars),
+            ("bytes", config.bytes),
+            ("max_line_length", config.max_line_length),
+        ]
+        .iter()
+        .filter(|(_, show)| *show)
+        .map(|&(name, _)| name)
+        .collect();
+
+        RecordWriter {
+            format,
+            columns,
+            wrote_header: false,
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        out: &mut impl Write,
+        path: Option<&str>,
+        result: Result<&FileInfo, &dyn Error>,
+    ) -> MyResult<()> {
+        let all_counts = match result {
+            Ok(info) => serde_json::to_value(info)?,
+            Err(_) => Value::Null,
+        };
+        let record = Record {
+            path,
+            counts: self
+                .columns
+                .iter()
+                .map(|&name| (name.to_string(), all_counts[name].clone()))
+                .collect(),
+            error: result.err().map(|e| e.to_string()),
+        };
+
+        if self.format == Format::Json {
+            serde_json::to_writer(&mut *out, &record)?;
+            out.write_all(b"\n")?;
+            return Ok(());
+        }
+
+        if !self.wrote_header {
+            writeln!(out, "path,{},error", self.columns.join(","))?;
+            self.wrote_header = true;
+        }
+        let counts: Vec<String> = self
+            .columns
+            .iter()
+            .map(|&name| match &record.counts[name] {
+                Value::Null => "".to_string(),
+                count => count.to_string(),
+            })
+            .collect();
+        writeln!(
+            out,
+            "{},{},{}",
+            quote(record.path.unwrap_or("")),
+            counts.join(","),
+            quote(record.error.as_deref().unwrap_or(""))
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Quotes a field the way RFC 4180 does when it contains a comma, a quote or
+/// a line break.
+fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
@@ -1,27 +1,77 @@
+use crate::{
+    counter::Counter,
+    format::{Format, RecordWriter},
+};
 use clap::{App, Arg};
+use memchr::memchr_iter;
+use serde::Serialize;
 use std::{
     error::Error,
-    fs::File,
-    io::{self, BufRead, BufReader},
+    ffi::{OsStr, OsString},
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, ErrorKind, Read},
+    path::{Path, PathBuf},
 };
 
+mod counter;
+mod format;
+mod parallel;
+mod progress;
+mod words;
+
+pub use words::{WordRegex, WordRule};
+
+const LINES_BUFFER_SIZE: usize = 256 * 1024;
+
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Debug)]
 pub struct Config {
-    files: Vec<String>,
+    files: Vec<OsString>,
+    files0_from: Option<PathBuf>,
     lines: bool,
     words: bool,
     bytes: bool,
     chars: bool,
+    max_line_length: bool,
+    total: Total,
+    jobs: usize,
+    format: Format,
+    word_rule: WordRule,
+    progress: bool,
 }
 
-#[derive(Debug, PartialEq)]
+/// When to print the `total` row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Total {
+    Auto,
+    Always,
+    Only,
+    Never,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct FileInfo {
+    #[serde(rename = "lines")]
     num_lines: usize,
+    #[serde(rename = "bytes")]
     num_bytes: usize,
+    #[serde(rename = "chars")]
     num_chars: usize,
+    #[serde(rename = "words")]
     num_words: usize,
+    max_line_length: usize,
+}
+
+impl FileInfo {
+    /// Adds the counts of another file, as for the total row.
+    pub fn add(&mut self, other: &FileInfo) {
+        self.num_lines += other.num_lines;
+        self.num_bytes += other.num_bytes;
+        self.num_chars += other.num_chars;
+        self.num_words += other.num_words;
+        self.max_line_length = self.max_line_length.max(other.max_line_length);
+    }
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -29,12 +79,13 @@ pub fn get_args() -> MyResult<Config> {
         .version("0.1.0")
         .author("mysteryven mysteryven@gmail.com")
         .about("Rust wc")
+        .arg(Arg::with_name("files").value_name("FILE").multiple(true))
         .arg(
-            Arg::with_name("files")
-                .value_name("FILE")
-                .required(true)
-                .default_value("-")
-                .multiple(true),
+            Arg::with_name("files0_from")
+                .value_name("F")
+                .help("Read NUL-separated file names from F, or from stdin if F is -")
+                .conflicts_with("files")
+                .long("files0-from"),
         )
         .arg(
             Arg::with_name("lines")
@@ -61,134 +112,420 @@ pub fn get_args() -> MyResult<Config> {
             Arg::with_name("chars")
                 .takes_value(false)
                 .help("Show character count")
-                .conflicts_with("bytes")
                 .short("m")
                 .long("chars"),
         )
+        .arg(
+            Arg::with_name("max_line_length")
+                .takes_value(false)
+                .help("Show the display width of the longest line")
+                .short("L")
+                .long("max-line-length"),
+        )
+        .arg(
+            Arg::with_name("total")
+                .value_name("WHEN")
+                .help("When to print a line with total counts")
+                .possible_values(&["auto", "always", "only", "never"])
+                .default_value("auto")
+                .long("total"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .value_name("N")
+                .help("Count with N threads")
+                .default_value("1")
+                .short("j")
+                .long("jobs"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .value_name("FORMAT")
+                .help("Output format, text by default; json and csv print one record per file")
+                .possible_values(&["text", "json", "csv"])
+                .default_value("text")
+                .long("format"),
+        )
+        .arg(
+            Arg::with_name("word_rule")
+                .value_name("RULE")
+                .help("How to find words: posix splits on whitespace, unicode uses UAX #29 word boundaries")
+                .possible_values(&["posix", "unicode"])
+                .long("word-rule"),
+        )
+        .arg(
+            Arg::with_name("word_regex")
+                .value_name("REGEX")
+                .help("Count matches of REGEX within each line as words")
+                .conflicts_with("word_rule")
+                .long("word-regex"),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .takes_value(false)
+                .help("Print running counts on stderr while reading, every 500ms at most")
+                .long("progress"),
+        )
         .get_matches();
 
     let mut lines = matches.is_present("lines");
     let mut bytes = matches.is_present("bytes");
     let mut words = matches.is_present("words");
     let chars = matches.is_present("chars");
+    let max_line_length = matches.is_present("max_line_length");
 
-    if [lines, bytes, chars, words].iter().all(|v| !v) {
+    if [lines, bytes, chars, words, max_line_length]
+        .iter()
+        .all(|v| !v)
+    {
         lines = true;
         words = true;
         bytes = true;
     }
 
+    let jobs = matches
+        .value_of("jobs")
+        .map(|val| match val.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("Invalid --jobs \"{}\"", val)),
+        })
+        .transpose()?
+        .unwrap_or(1);
+
+    let word_rule = match matches.value_of("word_regex") {
+        Some(pattern) => WordRule::Regex(
+            WordRegex::new(pattern).map_err(|_| format!("Invalid --word-regex \"{}\"", pattern))?,
+        ),
+        None if matches.value_of("word_rule") == Some("unicode") => WordRule::Unicode,
+        None => WordRule::Posix,
+    };
+
     Ok(Config {
-        files: matches.values_of_lossy("files").unwrap(),
+        files: matches
+            .values_of_os("files")
+            .map(|vals| vals.map(OsString::from).collect())
+            .unwrap_or_else(|| vec![OsString::from("-")]),
+        files0_from: matches.value_of_os("files0_from").map(PathBuf::from),
         lines,
         bytes,
         chars,
         words,
+        max_line_length,
+        total: match matches.value_of("total") {
+            Some("always") => Total::Always,
+            Some("only") => Total::Only,
+            Some("never") => Total::Never,
+            _ => Total::Auto,
+        },
+        jobs,
+        format: match matches.value_of("format") {
+            Some("json") => Format::Json,
+            Some("csv") => Format::Csv,
+            _ => Format::Text,
+        },
+        word_rule,
+        progress: matches.is_present("progress"),
     })
 }
 
-pub fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
+pub fn open(filename: &Path) -> MyResult<Box<dyn BufRead>> {
+    match filename.to_str() {
+        Some("-") => Ok(Box::new(BufReader::new(io::stdin()))),
         _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
     }
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let mut total_lines = 0;
-    let mut total_words = 0;
-    let mut total_bytes = 0;
-    let mut total_chars = 0;
+/// Counts one file, skipping the work the selected columns don't need: a
+/// regular file's size comes from its metadata when only bytes are shown,
+/// and only newlines are scanned for when just lines and bytes are shown.
+/// A single large file is split into pieces counted on `--jobs` threads,
+/// unless `--progress` asks for running counts.
+pub fn count_file(filename: &OsStr, config: &Config) -> MyResult<FileInfo> {
+    let needs_text = config.words || config.chars || config.max_line_length;
 
-    for filename in &config.files {
-        match open(&filename) {
-            Err(err) => eprintln!("{}: {}", &filename, err),
-            Ok(buf_reader) => {
-                if let Ok(info) = count(buf_reader) {
-                    println!(
-                        "{}{}{}{}{}",
-                        format_field(info.num_lines, config.lines),
-                        format_field(info.num_words, config.words),
-                        format_field(info.num_bytes, config.bytes),
-                        format_field(info.num_bytes, config.chars),
-                        if filename.as_str() == "-" {
-                            "".to_string()
-                        } else {
-                            format!(" {}", filename)
-                        }
-                    );
-
-                    total_lines += info.num_lines;
-                    total_words += info.num_words;
-                    total_bytes += info.num_bytes;
-                    total_chars += info.num_chars;
+    if filename == "-" {
+        let file = open(Path::new("-"))?;
+        return if config.progress {
+            progress::count_with_progress(filename, file, config)
+        } else if needs_text {
+            count(file, &config.word_rule)
+        } else {
+            count_lines(file)
+        };
+    }
+
+    let file = File::open(filename)?;
+    let metadata = file.metadata()?;
+    // Files such as those under /proc report a size of zero, so read them.
+    let size = Some(metadata.len()).filter(|&len| metadata.is_file() && len > 0);
+    let jobs = if config.files.len() == 1 {
+        parallel::num_chunks(size.unwrap_or(0), config.jobs)
+    } else {
+        1
+    };
+
+    match size {
+        Some(len) if !needs_text && !config.lines => Ok(FileInfo {
+            num_bytes: len as usize,
+            ..FileInfo::default()
+        }),
+        _ if config.progress => {
+            progress::count_with_progress(filename, BufReader::new(file), config)
+        }
+        Some(len) if jobs > 1 => {
+            parallel::count_chunked(filename, len, jobs, needs_text, &config.word_rule)
+        }
+        _ if needs_text => count(BufReader::new(file), &config.word_rule),
+        _ => count_lines(file),
+    }
+}
+
+/// Counts every file and prints the report, returning `false` if any file
+/// could not be read.
+pub fn run(mut config: Config) -> MyResult<bool> {
+    let mut total = FileInfo::default();
+    let mut success = true;
+
+    if let Some(source) = &config.files0_from {
+        let (files, valid) = read_files0(source)?;
+        config.files = files;
+        success &= valid;
+    }
+
+    let width = column_width(&config);
+    let mut records = RecordWriter::new(config.format, &config);
+    let mut write_error = None;
+    let mut report = |filename: &OsStr, result: MyResult<FileInfo>| {
+        let name = filename.to_string_lossy();
+        if let Err(err) = &result {
+            eprintln!("{}: {}", name, err);
+            success = false;
+        }
+
+        if config.format != Format::Text {
+            if config.total != Total::Only {
+                if let Err(e) = records.write(
+                    &mut io::stdout(),
+                    Some(&name),
+                    result.as_ref().map_err(|e| e.as_ref()),
+                ) {
+                    write_error.get_or_insert(e);
                 }
             }
+        } else if let Ok(info) = &result {
+            if config.total != Total::Only {
+                println!(
+                    "{}{}",
+                    format_info(info, &config, width),
+                    if filename == "-" {
+                        "".to_string()
+                    } else {
+                        format!(" {}", name)
+                    }
+                );
+            }
+        }
+
+        if let Ok(info) = &result {
+            total.add(info);
+        }
+    };
+
+    if config.jobs > 1 && config.files.len() > 1 && !config.progress {
+        parallel::count_files(&config, &mut report);
+    } else {
+        for filename in &config.files {
+            report(filename, count_file(filename, &config));
         }
     }
 
-    if config.files.len() > 1 {
-        println!(
-            "{}{}{}{} total",
-            format_field(total_lines, config.lines),
-            format_field(total_words, config.words),
-            format_field(total_bytes, config.bytes),
-            format_field(total_chars, config.chars)
-        );
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+
+    let show_total = match config.total {
+        Total::Auto => config.files.len() > 1,
+        Total::Always | Total::Only => true,
+        Total::Never => false,
+    };
+    if show_total {
+        match config.format {
+            Format::Text if config.total == Total::Only => {
+                println!("{}", format_info(&total, &config, width))
+            }
+            Format::Text => println!("{} total", format_info(&total, &config, width)),
+            _ => records.write(&mut io::stdout(), None, Ok(&total))?,
+        }
     }
 
-    Ok(())
+    Ok(success)
 }
 
-pub fn format_field(name: usize, show: bool) -> String {
-    if show {
-        format!("{:>8}", name)
-    } else {
-        "".to_string()
+/// Reads the NUL-separated file names in `source` for `--files0-from`,
+/// reporting names that can't be used. Returns the usable names and whether
+/// every name was usable.
+pub fn read_files0(source: &Path) -> MyResult<(Vec<OsString>, bool)> {
+    let mut list = vec![];
+    open(source)
+        .and_then(|mut file| Ok(file.read_to_end(&mut list)?))
+        .map_err(|e| format!("cannot open '{}' for reading: {}", source.display(), e))?;
+
+    if list.last() == Some(&0) {
+        list.pop();
+    }
+    if list.is_empty() {
+        return Ok((vec![], true));
+    }
+
+    let mut files = vec![];
+    let mut valid = true;
+    for (i, name) in list.split(|&b| b == 0).enumerate() {
+        let problem = match os_string(name) {
+            Some(name) if name.is_empty() => Some("invalid zero-length file name"),
+            Some(name) if name == "-" && source == Path::new("-") => {
+                Some("when reading file names from stdin, no file name of '-' allowed")
+            }
+            Some(name) => {
+                files.push(name);
+                None
+            }
+            None => Some("file name is not valid UTF-8"),
+        };
+
+        if let Some(problem) = problem {
+            eprintln!("{}:{}: {}", source.display(), i + 1, problem);
+            valid = false;
+        }
     }
+
+    Ok((files, valid))
 }
 
-pub fn count(mut file: impl BufRead) -> MyResult<FileInfo> {
-    let mut num_lines = 0;
-    let mut num_bytes = 0;
-    let mut num_chars = 0;
-    let mut num_words = 0;
+/// Makes a file name from its raw bytes, which on Unix needn't be UTF-8.
+#[cfg(unix)]
+fn os_string(bytes: &[u8]) -> Option<OsString> {
+    use std::os::unix::ffi::OsStrExt;
 
-    let mut line = String::new();
+    Some(OsStr::from_bytes(bytes).to_os_string())
+}
 
-    loop {
-        let bytes = file.read_line(&mut line)?;
+#[cfg(not(unix))]
+fn os_string(bytes: &[u8]) -> Option<OsString> {
+    String::from_utf8(bytes.to_vec()).ok().map(OsString::from)
+}
+
+/// Picks a column width that fits every count, the way GNU wc does: the
+/// number of digits in the combined size of the regular files, but at least
+/// 7 when an input's size isn't known ahead of time, such as a pipe. A single
+/// column for a single file is printed without padding.
+pub fn column_width(config: &Config) -> usize {
+    let columns = [
+        config.lines,
+        config.words,
+        config.chars,
+        config.bytes,
+        config.max_line_length,
+    ]
+    .iter()
+    .filter(|&&show| show)
+    .count();
+
+    if config.files.len() == 1 && columns == 1 {
+        return 1;
+    }
+
+    let mut minimum_width = 1;
+    let mut regular_total: u64 = 0;
+    for filename in &config.files {
+        let metadata = match filename.to_str() {
+            Some("-") => stdin_metadata(),
+            _ => fs::metadata(filename).ok(),
+        };
 
-        if bytes == 0 {
-            break;
+        match metadata {
+            Some(metadata) if metadata.is_file() => {
+                regular_total = regular_total.saturating_add(metadata.len())
+            }
+            Some(_) => minimum_width = 7,
+            None => {}
         }
+    }
+
+    regular_total.to_string().len().max(minimum_width)
+}
+
+#[cfg(unix)]
+fn stdin_metadata() -> Option<Metadata> {
+    use std::os::fd::AsFd;
+
+    let fd = io::stdin().as_fd().try_clone_to_owned().ok()?;
+    File::from(fd).metadata().ok()
+}
+
+#[cfg(not(unix))]
+fn stdin_metadata() -> Option<Metadata> {
+    None
+}
+
+/// Formats the selected columns in wc order: lines, words, chars, bytes and
+/// max line length.
+pub fn format_info(info: &FileInfo, config: &Config, width: usize) -> String {
+    [
+        (info.num_lines, config.lines),
+        (info.num_words, config.words),
+        (info.num_chars, config.chars),
+        (info.num_bytes, config.bytes),
+        (info.max_line_length, config.max_line_length),
+    ]
+    .iter()
+    .filter(|(_, show)| *show)
+    .map(|&(value, _)| format_field(value, width))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+pub fn format_field(value: usize, width: usize) -> String {
+    format!("{:>width$}", value, width = width)
+}
+
+pub fn count(file: impl BufRead, rule: &WordRule) -> MyResult<FileInfo> {
+    let mut counter = Counter::new(rule);
+    counter.read_all(file)?;
+    Ok(counter.finish())
+}
+
+/// Counts only lines and bytes, searching large buffers for newlines with
+/// `memchr` instead of decoding the text.
+pub fn count_lines(mut file: impl Read) -> MyResult<FileInfo> {
+    let mut info = FileInfo::default();
+    let mut buf = vec![0; LINES_BUFFER_SIZE];
+
+    loop {
+        let bytes = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
 
-        num_lines += 1;
-        num_bytes += bytes;
-        num_chars += line.chars().count();
-        num_words += line.split_whitespace().count();
-        line.clear();
+        info.num_bytes += bytes;
+        info.num_lines += memchr_iter(b'\n', &buf[..bytes]).count();
     }
 
-    Ok(FileInfo {
-        num_lines,
-        num_bytes,
-        num_chars,
-        num_words,
-    })
+    Ok(info)
 }
 
 #[cfg(test)]
 mod test {
     use crate::format_field;
 
-    use super::{count, FileInfo};
+    use super::{count, count_lines, Counter, FileInfo, WordRegex, WordRule};
     use std::io::Cursor;
 
     #[test]
     fn test_count() {
         let text = "I don't want the world. I just want your half.\r\n";
-        let info = count(Cursor::new(text));
+        let info = count(Cursor::new(text), &WordRule::Posix);
         assert!(info.is_ok());
 
         let expected = FileInfo {
@@ -196,15 +533,128 @@ mod test {
             num_words: 10,
             num_chars: 48,
             num_bytes: 48,
+            max_line_length: 46,
         };
 
         assert_eq!(info.unwrap(), expected)
     }
 
+    #[test]
+    fn test_count_lines() {
+        let text = "I don't want the world.\nI just want your half.\r\n";
+        let info = count_lines(Cursor::new(text)).unwrap();
+        assert_eq!(info.num_lines, 2);
+        assert_eq!(info.num_bytes, 48);
+    }
+
     #[test]
     fn test_format_field() {
-        assert_eq!(format_field(1, true), "       1");
-        assert_eq!(format_field(1, false), "");
-        assert_eq!(format_field(10, true), "      10")
+        assert_eq!(format_field(1, 1), "1");
+        assert_eq!(format_field(1, 7), "      1");
+        assert_eq!(format_field(123456789, 7), "123456789")
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        let width = |text: &str| {
+            count(Cursor::new(text), &WordRule::Posix)
+                .unwrap()
+                .max_line_length
+        };
+        assert_eq!(width("fox\n"), 3);
+        assert_eq!(width("ab\tc\n"), 9);
+        assert_eq!(width("世界\n"), 4);
+        assert_eq!(width("long line\rshort\n"), 9);
+    }
+
+    #[test]
+    fn test_counter_append() {
+        let text = "a\tb c\u{4e16} \u{754c}\td\ref  \u{1f389}\n\tx\u{e9}\u{e9}\ty\n  z";
+        let expected = count(Cursor::new(text), &WordRule::Posix).unwrap();
+
+        for (first, _) in text.char_indices() {
+            for (second, _) in text[first..].char_indices() {
+                let pieces = [
+                    &text[..first],
+                    &text[first..][..second],
+                    &text[first..][second..],
+                ];
+                let mut counter = Counter::new(&WordRule::Posix);
+                for piece in pieces {
+                    let mut next = Counter::new(&WordRule::Posix);
+                    next.update(piece.as_bytes());
+                    counter.append(next);
+                }
+                assert_eq!(counter.finish(), expected, "split at {:?}", pieces);
+            }
+        }
+    }
+
+    #[test]
+    fn test_counter_append_rules() {
+        let text = "don't stop\u{2014}the \u{4e16}\u{754c}\nfoo.bar 3,14\n\n\u{65e5}\u{672c} x";
+        let rules = [
+            WordRule::Unicode,
+            WordRule::Regex(WordRegex::new("[a-z]+").unwrap()),
+        ];
+
+        // These rules are only ever split at line starts.
+        let starts: Vec<_> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        for rule in &rules {
+            let expected = count(Cursor::new(text), rule).unwrap();
+            for &first in &starts {
+                for &second in starts.iter().filter(|&&second| second >= first) {
+                    let pieces = [&text[..first], &text[first..second], &text[second..]];
+                    let mut counter = Counter::new(rule);
+                    for piece in pieces {
+                        let mut next = Counter::new(rule);
+                        next.update(piece.as_bytes());
+                        counter.append(next);
+                    }
+                    assert_eq!(counter.finish(), expected, "split at {:?}", pieces);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_separates_words() {
+        let rules = [
+            WordRule::Unicode,
+            WordRule::Regex(WordRegex::new("[a-z]+").unwrap()),
+        ];
+        for rule in &rules {
+            let info = count(Cursor::new(b"ab\xffcd ef\n"), rule).unwrap();
+            assert_eq!(info.num_words, 3);
+        }
+    }
+
+    #[test]
+    fn test_count_split_and_invalid() {
+        let text = "caf\u{e9} \u{4e16}\u{754c}\n\u{1f389}\n".as_bytes();
+        let mut expected = Counter::new(&WordRule::Posix);
+        expected.update(text);
+        let expected = expected.finish();
+
+        for split in 0..text.len() {
+            let mut counter = Counter::new(&WordRule::Posix);
+            counter.update(&text[..split]);
+            counter.update(&text[split..]);
+            assert_eq!(counter.finish(), expected);
+        }
+
+        let info = count(Cursor::new(b"ok \xff\xfe x\xe4\xb8\n"), &WordRule::Posix).unwrap();
+        assert_eq!(
+            info,
+            FileInfo {
+                num_lines: 1,
+                num_words: 2,
+                num_chars: 6,
+                num_bytes: 10,
+                max_line_length: 5,
+            }
+        );
     }
 }
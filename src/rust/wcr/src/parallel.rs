@@ -0,0 +1,166 @@
+use crate::{count_file, count_lines, counter::Counter, Config, FileInfo, MyResult, WordRule};
+use memchr::memchr;
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+/// Pieces smaller than this aren't worth a thread of their own.
+const MIN_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Returns how many pieces a file of `len` bytes should be counted in.
+pub fn num_chunks(len: u64, jobs: usize) -> usize {
+    jobs.min((len / MIN_CHUNK_SIZE) as usize).max(1)
+}
+
+/// Counts `config.files` on `config.jobs` threads, calling `report` for each
+/// file in argument order as soon as it and every file before it are done.
+pub fn count_files(config: &Config, mut report: impl FnMut(&OsStr, MyResult<FileInfo>)) {
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..config.jobs.min(config.files.len()) {
+            let (tx, next) = (tx.clone(), &next);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(filename) = config.files.get(i) else {
+                    break;
+                };
+
+                // Box<dyn Error> isn't Send, so errors cross threads as text.
+                let result = count_file(filename, config).map_err(|e| e.to_string());
+                if tx.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut done: Vec<Option<Result<FileInfo, String>>> =
+            config.files.iter().map(|_| None).collect();
+        let mut printed = 0;
+        for (i, result) in rx {
+            done[i] = Some(result);
+            while let Some(result) = done.get_mut(printed).and_then(Option::take) {
+                report(&config.files[printed], result.map_err(Into::into));
+                printed += 1;
+            }
+        }
+    });
+}
+
+/// Counts the regular file `path` of `len` bytes in `jobs` pieces at once.
+/// Pieces start on character boundaries, or at lines for word rules other
+/// than POSIX, and [`Counter::append`] accounts for words and lines that run
+/// across them.
+pub fn count_chunked(
+    path: &OsStr,
+    len: u64,
+    jobs: usize,
+    needs_text: bool,
+    rule: &WordRule,
+) -> MyResult<FileInfo> {
+    let by_line = needs_text && !matches!(rule, WordRule::Posix);
+    let bounds = chunk_bounds(path, len, jobs, by_line)?;
+
+    let pieces = thread::scope(|scope| {
+        let handles: Vec<_> = bounds
+            .windows(2)
+            .map(|range| {
+                let (start, end) = (range[0], range[1]);
+                scope.spawn(move || {
+                    count_range(path, start, end, needs_text, rule).map_err(|e| e.to_string())
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("counting thread panicked"))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let mut pieces = pieces.into_iter();
+    let mut counter = pieces.next().unwrap_or_default();
+    for piece in pieces {
+        counter.append(piece);
+    }
+
+    Ok(counter.finish())
+}
+
+fn count_range(
+    path: &OsStr,
+    start: u64,
+    end: u64,
+    needs_text: bool,
+    rule: &WordRule,
+) -> MyResult<Counter> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let piece = file.take(end - start);
+
+    if needs_text {
+        let mut counter = Counter::new(rule);
+        counter.read_all(BufReader::new(piece))?;
+        Ok(counter)
+    } else {
+        Ok(Counter::from(count_lines(piece)?))
+    }
+}
+
+/// Splits `len` bytes into `jobs` roughly equal ranges, moving each split
+/// forward past UTF-8 continuation bytes so no character is cut in two, or
+/// to the start of the next line if `by_line` is set.
+fn chunk_bounds(path: &OsStr, len: u64, jobs: usize, by_line: bool) -> MyResult<Vec<u64>> {
+    let mut file = File::open(path)?;
+    let mut bounds = vec![0];
+
+    for i in 1..jobs as u64 {
+        let mut offset = len * i / jobs as u64;
+        if by_line {
+            offset = offset.saturating_sub(1);
+            file.seek(SeekFrom::Start(offset))?;
+            offset += next_line(&mut file)?;
+        } else {
+            let mut buf = [0; 3];
+            file.seek(SeekFrom::Start(offset))?;
+            let read = file.read(&mut buf)?;
+            offset += buf[..read]
+                .iter()
+                .take_while(|&&b| b & 0b1100_0000 == 0b1000_0000)
+                .count() as u64;
+        }
+
+        if offset > *bounds.last().unwrap() && offset < len {
+            bounds.push(offset);
+        }
+    }
+
+    bounds.push(len);
+    Ok(bounds)
+}
+
+/// Returns how many bytes past the position of `file` the next line starts,
+/// or the number left if there is no newline.
+fn next_line(file: &mut File) -> io::Result<u64> {
+    let mut buf = [0; 8192];
+    let mut skipped = 0;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(skipped);
+        }
+        if let Some(i) = memchr(b'\n', &buf[..read]) {
+            return Ok(skipped + i as u64 + 1);
+        }
+        skipped += read as u64;
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{column_width, counter::Counter, format_info, Config, FileInfo, MyResult};
+use std::{
+    ffi::OsStr,
+    io::{self, BufRead, IsTerminal, Write},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often running counts are printed.
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// Prints the running counts of one input on stderr while it is read. On a
+/// terminal the same line is rewritten; otherwise each update is a new line.
+pub struct Progress<'a> {
+    config: &'a Config,
+    label: String,
+    width: usize,
+    interactive: bool,
+    shown: bool,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(filename: &OsStr, config: &'a Config) -> Self {
+        Progress {
+            config,
+            label: match filename.to_str() {
+                Some("-") => "".to_string(),
+                _ => format!(" {}", filename.to_string_lossy()),
+            },
+            width: column_width(config),
+            interactive: io::stderr().is_terminal(),
+            shown: false,
+        }
+    }
+
+    pub fn show(&mut self, info: &FileInfo) {
+        self.shown = true;
+
+        let line = format!(
+            "{}{}",
+            format_info(info, self.config, self.width),
+            self.label
+        );
+        let mut stderr = io::stderr().lock();
+        // Progress is best effort, so a closed stderr doesn't stop counting.
+        let _ = if self.interactive {
+            write!(stderr, "\r{}\x1b[K", line).and_then(|_| stderr.flush())
+        } else {
+            writeln!(stderr, "{}", line)
+        };
+    }
+}
+
+/// Clears the progress line so the final counts print cleanly.
+impl Drop for Progress<'_> {
+    fn drop(&mut self) {
+        if self.interactive && self.shown {
+            let _ = write!(io::stderr(), "\r\x1b[K");
+        }
+    }
+}
+
+/// Counts everything left to read in `file` a buffer at a time, printing
+/// the running counts every [`INTERVAL`] from another thread, so they show
+/// even while reading is blocked waiting for input.
+pub fn count_with_progress(
+    filename: &OsStr,
+    mut file: impl BufRead,
+    config: &Config,
+) -> MyResult<FileInfo> {
+    let mut counter = Counter::new(&config.word_rule);
+    // The counts not yet shown, if they changed since the last update.
+    let latest = &Mutex::new(None);
+    let (done, ticks) = mpsc::channel::<()>();
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut progress = Progress::new(filename, config);
+            while let Err(RecvTimeoutError::Timeout) = ticks.recv_timeout(INTERVAL) {
+                if let Some(info) = latest.lock().unwrap().take() {
+                    progress.show(&info);
+                }
+            }
+        });
+
+        let result = read_all(&mut file, &mut counter, latest);
+        drop(done);
+        result
+    })?;
+
+    Ok(counter.finish())
+}
+
+fn read_all(
+    file: &mut impl BufRead,
+    counter: &mut Counter,
+    latest: &Mutex<Option<FileInfo>>,
+) -> io::Result<()> {
+    loop {
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let len = buf.len();
+        counter.update(buf);
+        file.consume(len);
+        *latest.lock().unwrap() = Some(counter.counts());
+    }
+}
@@ -0,0 +1,480 @@
+use regex::bytes::Regex;
+use regex_automata::{
+    hybrid::{
+        dfa::{Cache, DFA},
+        LazyStateID,
+    },
+    Input,
+};
+use regex_syntax::hir::{Class, HirKind, Literal};
+use std::{
+    cmp::Ordering,
+    str,
+    sync::{Arc, OnceLock},
+};
+
+/// How far the lazy DFA may read past the start of a search without a match
+/// before [`Matcher`] checks whether it can start the search over.
+const CUT_AFTER: usize = 64;
+
+/// How many bytes [`Matcher`] keeps before a search start for look-behind.
+const LOOK_BEHIND: usize = 4;
+
+/// What counts as a word.
+#[derive(Debug, Clone, Default)]
+pub enum WordRule {
+    /// Runs of characters other than whitespace, as in POSIX wc.
+    #[default]
+    Posix,
+    /// Words found by Unicode word segmentation (UAX #29), which splits
+    /// ideographic text into single characters and keeps `don't` whole.
+    Unicode,
+    /// Non-empty matches of a regular expression, which can't span lines.
+    Regex(WordRegex),
+}
+
+/// A regular expression for [`WordRule::Regex`]. Matches are found in the
+/// raw bytes of each line, so invalid UTF-8 separates words rather than
+/// disappearing from between them.
+#[derive(Debug, Clone)]
+pub struct WordRegex {
+    regex: Regex,
+    /// The same pattern as a lazy DFA, which tells [`Matcher`] when the text
+    /// read so far can no longer change the matches found in it. None if
+    /// the pattern can't be built as one.
+    dfa: Option<Arc<DFA>>,
+}
+
+impl WordRegex {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        let dfa = DFA::builder()
+            .configure(DFA::config().unicode_word_boundary(true))
+            .build(pattern)
+            .ok()
+            .map(Arc::new);
+        Ok(WordRegex { regex, dfa })
+    }
+
+    /// Counts the non-empty matches in `line` from `start` on. Like
+    /// `find_iter`, an empty match moves the next search on by a byte.
+    fn count(&self, line: &[u8], mut start: usize) -> usize {
+        let mut count = 0;
+        while start <= line.len() {
+            match self.regex.find_at(line, start) {
+                Some(m) if m.start() == m.end() => start = m.end() + 1,
+                Some(m) => {
+                    count += 1;
+                    start = m.end();
+                }
+                None => break,
+            }
+        }
+        count
+    }
+}
+
+/// Counts the matches of a [`WordRegex`] in input fed a buffer at a time.
+/// Only the current line is searched, and of it only the text since the
+/// last point the lazy DFA showed to be safe to start over from: the end of
+/// a match, or a byte after which no match is under way. Patterns the DFA
+/// gives up on, such as `\b` next to non-ASCII text, keep the rest of the
+/// line instead.
+#[derive(Debug, Clone)]
+pub(crate) struct Matcher {
+    rule: WordRegex,
+    cache: Option<Cache>,
+    /// The unsearched text of the current line, from up to [`LOOK_BEHIND`]
+    /// bytes before `start`.
+    buf: Vec<u8>,
+    /// Where in `buf` the next search starts.
+    start: usize,
+    /// How far into `buf` the DFA has read, and the state it is in there.
+    /// None once the DFA has given up on this line.
+    pos: usize,
+    state: Option<LazyStateID>,
+    /// Whether the DFA has found a match since `start`.
+    matched: bool,
+}
+
+impl Matcher {
+    pub fn new(rule: &WordRegex) -> Self {
+        let mut matcher = Matcher {
+            rule: rule.clone(),
+            cache: rule.dfa.as_ref().map(|dfa| dfa.create_cache()),
+            buf: vec![],
+            start: 0,
+            pos: 0,
+            state: None,
+            matched: false,
+        };
+        matcher.restart();
+        matcher
+    }
+
+    /// Reads `input`, returning the number of words it completed.
+    pub fn update(&mut self, mut input: &[u8]) -> usize {
+        let mut words = 0;
+        while let Some(i) = memchr::memchr(b'\n', input) {
+            words += self.extend(&input[..i]) + self.pending();
+            self.buf.clear();
+            self.start = 0;
+            self.restart();
+            input = &input[i + 1..];
+        }
+        words + self.extend(input)
+    }
+
+    /// Returns the number of words in the rest of the line, as if it ended
+    /// here.
+    pub fn pending(&self) -> usize {
+        self.rule.count(&self.buf, self.start)
+    }
+
+    fn extend(&mut self, text: &[u8]) -> usize {
+        self.buf.extend_from_slice(text);
+        let mut words = 0;
+
+        while let (Some(state), Some(dfa), Some(cache)) =
+            (self.state, &self.rule.dfa, &mut self.cache)
+        {
+            let Some(&byte) = self.buf.get(self.pos) else {
+                break;
+            };
+            self.pos += 1;
+            self.state = dfa.next_state(cache, state, byte).ok();
+
+            match self.state {
+                Some(next) if next.is_dead() => words += self.settle(),
+                Some(next) if next.is_quit() => self.state = None,
+                Some(next) if next.is_match() => self.matched = true,
+                Some(next) if !self.matched && self.pos - self.start >= CUT_AFTER => {
+                    // Back in the state a search from here would start in,
+                    // so no match began before here.
+                    let input = Input::new(&self.buf).range(self.pos..);
+                    if dfa.start_state_forward(cache, &input).ok() == Some(next) {
+                        self.start = self.pos;
+                        self.trim();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        words
+    }
+
+    /// Finds the match the DFA just stopped at and starts over after it.
+    fn settle(&mut self) -> usize {
+        // The DFA read the byte before `pos` only to see that the match
+        // couldn't go on, which is all the look-ahead a search needs.
+        let words = match self.rule.regex.find_at(&self.buf[..self.pos], self.start) {
+            Some(m) if m.start() == m.end() => {
+                self.start = m.end() + 1;
+                0
+            }
+            Some(m) => {
+                self.start = m.end();
+                1
+            }
+            None => {
+                self.start = self.pos;
+                0
+            }
+        };
+        self.trim();
+        self.restart();
+        words
+    }
+
+    /// Drops the searched text, once there is enough of it to be worth it.
+    fn trim(&mut self) {
+        if self.start >= CUT_AFTER + LOOK_BEHIND {
+            let drop = self.start - LOOK_BEHIND;
+            self.buf.drain(..drop);
+            self.start -= drop;
+            self.pos -= drop;
+        }
+    }
+
+    /// Points the DFA at the start of the next search.
+    fn restart(&mut self) {
+        self.pos = self.start;
+        self.matched = false;
+        self.state = match (&self.rule.dfa, &mut self.cache) {
+            (Some(dfa), Some(cache)) => {
+                let input = Input::new(&self.buf).range(self.start..);
+                dfa.start_state_forward(cache, &input).ok()
+            }
+            _ => None,
+        };
+    }
+}
+
+/// The classes of the Word_Break property (UAX #29) that can join
+/// characters into words. Every other class behaves like `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WordBreak {
+    Other,
+    Newline,
+    /// Extend, Format and ZWJ, which are skipped over by rule WB4.
+    Ignorable,
+    ALetter,
+    HebrewLetter,
+    Numeric,
+    Katakana,
+    ExtendNumLet,
+    MidLetter,
+    MidNum,
+    MidNumLet,
+    SingleQuote,
+    DoubleQuote,
+}
+
+impl WordBreak {
+    fn of(c: char) -> WordBreak {
+        static TABLE: OnceLock<Vec<(char, char, WordBreak)>> = OnceLock::new();
+
+        let table = TABLE.get_or_init(|| {
+            use WordBreak::*;
+            let classes = [
+                ("CR", Newline),
+                ("LF", Newline),
+                ("Newline", Newline),
+                ("Extend", Ignorable),
+                ("Format", Ignorable),
+                ("ZWJ", Ignorable),
+                ("ALetter", ALetter),
+                ("Hebrew_Letter", HebrewLetter),
+                ("Numeric", Numeric),
+                ("Katakana", Katakana),
+                ("ExtendNumLet", ExtendNumLet),
+                ("MidLetter", MidLetter),
+                ("MidNum", MidNum),
+                ("MidNumLet", MidNumLet),
+                ("Single_Quote", SingleQuote),
+                ("Double_Quote", DoubleQuote),
+            ];
+
+            let mut table = vec![];
+            for (name, class) in classes {
+                let hir = regex_syntax::parse(&format!(r"\p{{Word_Break={}}}", name))
+                    .expect("Word_Break value");
+                match hir.kind() {
+                    HirKind::Class(Class::Unicode(set)) => {
+                        table.extend(set.ranges().iter().map(|r| (r.start(), r.end(), class)));
+                    }
+                    // A class of one character, such as ZWJ.
+                    HirKind::Literal(Literal(bytes)) => {
+                        let c = str::from_utf8(bytes).ok().and_then(|s| s.chars().next());
+                        table.extend(c.map(|c| (c, c, class)));
+                    }
+                    _ => {}
+                }
+            }
+            table.sort_unstable_by_key(|&(start, _, _)| start);
+            table
+        });
+
+        let found = table.binary_search_by(|&(start, end, _)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+        found.map_or(WordBreak::Other, |i| table[i].2)
+    }
+
+    fn is_ahletter(self) -> bool {
+        matches!(self, WordBreak::ALetter | WordBreak::HebrewLetter)
+    }
+
+    /// Whether there is no word boundary between `self` and `next` (WB5,
+    /// WB8 to WB10, WB13 to WB13b).
+    fn joins(self, next: WordBreak) -> bool {
+        use WordBreak::*;
+        matches!(
+            (self, next),
+            (
+                ALetter | HebrewLetter | Numeric,
+                ALetter | HebrewLetter | Numeric
+            ) | (Katakana, Katakana)
+                | (
+                    ALetter | HebrewLetter | Numeric | Katakana | ExtendNumLet,
+                    ExtendNumLet
+                )
+                | (ExtendNumLet, ALetter | HebrewLetter | Numeric | Katakana)
+        )
+    }
+}
+
+/// Counts words by Unicode word segmentation a character at a time. As
+/// with `unicode_words`, a word is a segment with a letter or digit in it,
+/// and it is counted as soon as one turns up. The only character held back
+/// is one like the `'` in `don't`, which belongs to the word before it only
+/// if a letter follows.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Segmenter {
+    /// The class of the last character that wasn't ignorable, or None at
+    /// the start of a line.
+    last: Option<WordBreak>,
+    /// Whether the current segment has been counted.
+    counted: bool,
+    mid: Option<Mid>,
+}
+
+/// A character waiting on the next one to tell whether it joins the segment
+/// before it (WB6, WB7, WB7b, WB7c, WB11, WB12).
+#[derive(Debug, Clone, Copy)]
+struct Mid {
+    class: WordBreak,
+    /// The class the next character needs: letters for letters, numbers for
+    /// numbers, and a Hebrew letter after a Hebrew letter and `"`.
+    needs: fn(WordBreak) -> bool,
+    /// Whether it, or a character skipped over after it, is alphanumeric.
+    alnum: bool,
+    /// Whether it already joined the segment before it, as a `'` after a
+    /// Hebrew letter does (WB7a).
+    joined: bool,
+}
+
+impl Segmenter {
+    /// Reads `c`, returning the number of words it started.
+    pub fn push(&mut self, c: char) -> usize {
+        use WordBreak::*;
+        let class = WordBreak::of(c);
+        let alnum = c.is_alphanumeric();
+
+        if class == Ignorable && self.last.is_some() {
+            return match &mut self.mid {
+                Some(mid) if !mid.joined => {
+                    mid.alnum |= alnum;
+                    0
+                }
+                _ => self.extend(alnum),
+            };
+        }
+
+        let mut words = 0;
+        if let Some(mid) = self.mid.take() {
+            if (mid.needs)(class) {
+                self.last = Some(class);
+                let mid_alnum = mid.alnum && !mid.joined;
+                return self.extend(mid_alnum) + self.extend(alnum);
+            }
+            if !mid.joined {
+                self.counted = false;
+                words += self.extend(mid.alnum);
+            }
+            self.last = Some(mid.class);
+        }
+
+        let needs: Option<fn(WordBreak) -> bool> = match (self.last, class) {
+            (Some(last), MidLetter | MidNumLet | SingleQuote) if last.is_ahletter() => {
+                Some(WordBreak::is_ahletter)
+            }
+            (Some(Numeric), MidNum | MidNumLet | SingleQuote) => Some(|next| next == Numeric),
+            (Some(HebrewLetter), DoubleQuote) => Some(|next| next == HebrewLetter),
+            _ => None,
+        };
+        if let Some(needs) = needs {
+            let joined = self.last == Some(HebrewLetter) && class == SingleQuote;
+            self.mid = Some(Mid {
+                class,
+                needs,
+                alnum,
+                joined,
+            });
+            if joined {
+                words += self.extend(alnum);
+            }
+            return words;
+        }
+
+        if !self.last.is_some_and(|last| last.joins(class)) {
+            self.counted = false;
+        }
+        words += self.extend(alnum);
+        self.last = (class != Newline).then_some(class);
+        words
+    }
+
+    /// Returns the number of words held back, as if the input ended here.
+    pub fn pending(&self) -> usize {
+        self.mid
+            .map_or(0, |mid| usize::from(mid.alnum && !mid.joined))
+    }
+
+    /// Adds a character to the current segment.
+    fn extend(&mut self, alnum: bool) -> usize {
+        if alnum && !self.counted {
+            self.counted = true;
+            1
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Matcher, Segmenter, WordRegex};
+    use rand::{seq::SliceRandom, Rng};
+    use unicode_segmentation::UnicodeSegmentation;
+
+    #[test]
+    fn test_segmenter_matches_unicode_words() {
+        let pieces = [
+            "a",
+            "Z",
+            "7",
+            "\u{5d0}",
+            "\u{30ab}",
+            "_",
+            "'",
+            "\"",
+            ".",
+            ",",
+            ":",
+            " ",
+            "\n",
+            "\r",
+            "\u{301}",
+            "\u{200d}",
+            "\u{4e16}",
+            "\u{ff10}",
+            "\u{1f389}",
+            "-",
+        ];
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..2000 {
+            let len = rng.gen_range(0..12);
+            let text: String = (0..len)
+                .map(|_| *pieces.choose(&mut rng).unwrap())
+                .collect();
+
+            let mut segmenter = Segmenter::default();
+            let mut words: usize = text.chars().map(|c| segmenter.push(c)).sum();
+            words += segmenter.pending();
+
+            let expected: usize = text.lines().map(|line| line.unicode_words().count()).sum();
+            assert_eq!(words, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_matcher_bounded() {
+        let rule = WordRegex::new("[a-z]+").unwrap();
+        let mut matcher = Matcher::new(&rule);
+        let mut words = 0;
+        for _ in 0..10_000 {
+            words += matcher.update(b"foo, bar ");
+        }
+        assert_eq!(words + matcher.pending(), 20_000);
+        assert!(matcher.buf.len() < 1024, "held {} bytes", matcher.buf.len());
+    }
+}
@@ -9,6 +9,9 @@ const PRG: &str = "wcr";
 const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const ATLAMAL: &str = "tests/inputs/atlamal.txt";
+const MULTIBYTE: &str = "tests/inputs/multibyte.txt";
+const INVALID: &str = "tests/inputs/invalid.txt";
+const WORDS: &str = "tests/inputs/words.txt";
 
 // --------------------------------------------------
 fn gen_bad_file() -> String {
@@ -25,19 +28,6 @@ fn gen_bad_file() -> String {
     }
 }
 
-// --------------------------------------------------
-#[test]
-fn dies_chars_and_bytes() -> TestResult {
-    Command::cargo_bin(PRG)?
-        .args(&["-m", "-c"])
-        .assert()
-        .failure()
-        .stderr(predicate::str::contains(
-            "The argument '--bytes' cannot be used with '--chars'",
-        ));
-    Ok(())
-}
-
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
     let expected = fs::read_to_string(expected_file)?;
@@ -55,9 +45,10 @@ fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .arg(bad)
+        .args([&bad, FOX])
         .assert()
-        .success()
+        .failure()
+        .stdout(predicate::str::contains(FOX))
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
@@ -171,6 +162,34 @@ fn atlamal_stdin() -> TestResult {
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn fox_stdin_file() -> TestResult {
+    use assert_cmd::cargo::CommandCargoExt;
+
+    let output = std::process::Command::cargo_bin(PRG)?
+        .stdin(fs::File::open(FOX)?)
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, " 1  9 48\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pipe_and_file_widths() -> TestResult {
+    let input = fs::read_to_string(FOX)?;
+    Command::cargo_bin(PRG)?
+        .args(["-", FOX])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "      1       9      48\n      1       9      48 tests/inputs/fox.txt\n      2      18      96 total\n",
+        );
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn test_all() -> TestResult {
@@ -212,3 +231,405 @@ fn test_all_words_lines() -> TestResult {
 fn test_all_bytes_lines() -> TestResult {
     run(&["-cl", EMPTY, FOX, ATLAMAL], "tests/expected/all.cl.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn multibyte() -> TestResult {
+    run(&[MULTIBYTE], "tests/expected/multibyte.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_chars() -> TestResult {
+    run(&["-m", MULTIBYTE], "tests/expected/multibyte.txt.m.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_bytes() -> TestResult {
+    run(&["-c", MULTIBYTE], "tests/expected/multibyte.txt.c.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_chars_bytes() -> TestResult {
+    run(
+        &["-c", "-m", MULTIBYTE],
+        "tests/expected/multibyte.txt.cm.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_lines_words_chars() -> TestResult {
+    run(&["-lwm", MULTIBYTE], "tests/expected/multibyte.txt.lwm.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_fox_chars_bytes() -> TestResult {
+    run(
+        &["-cm", MULTIBYTE, FOX],
+        "tests/expected/multibyte_fox.cm.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fox_max_line_length() -> TestResult {
+    run(
+        &["-L", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.L.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_lines_max_line_length() -> TestResult {
+    run(&["-lL", MULTIBYTE], "tests/expected/multibyte.txt.lL.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn fox_total_always() -> TestResult {
+    run(
+        &["--total=always", FOX],
+        "tests/expected/fox.txt.total_always.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn test_total_always() -> TestResult {
+    run(
+        &["--total=always", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.total_always.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn test_total_only() -> TestResult {
+    run(
+        &["--total=only", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.total_only.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn test_total_never() -> TestResult {
+    run(
+        &["--total=never", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.total_never.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8() -> TestResult {
+    run(&["-lwmcL", INVALID], "tests/expected/invalid.txt.lwmcL.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn reports_unreadable_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", FOX])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(FOX))
+        .stderr(predicate::str::starts_with("tests/inputs: "));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-j", "0", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --jobs \"0\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn test_all_jobs() -> TestResult {
+    run(
+        &["-j", "3", "-lwmcL", EMPTY, FOX, MULTIBYTE, INVALID],
+        "tests/expected/all_jobs.lwmcL.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn large_file_jobs() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("large.txt");
+    let mut text = fs::read(MULTIBYTE)?;
+    text.extend(fs::read(INVALID)?);
+    text.extend(b"\tword\tword  ");
+    fs::write(&path, text.repeat(60_000))?;
+    let path = path.to_str().unwrap();
+
+    for args in [["-lwmcL"], ["-l"]] {
+        let sequential = Command::cargo_bin(PRG)?.args(args).arg(path).output()?;
+        assert!(sequential.status.success());
+        Command::cargo_bin(PRG)?
+            .args(["-j", "4"])
+            .args(args)
+            .arg(path)
+            .assert()
+            .success()
+            .stdout(sequential.stdout);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from() -> TestResult {
+    run(
+        &["--files0-from", "tests/inputs/files0.txt"],
+        "tests/expected/files0.txt.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from_stdin() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/files0.txt.out")?;
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from=-")
+        .pipe_stdin("tests/inputs/files0.txt")?
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn files0_from_non_utf8_name() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::write(&path, "one two\n")?;
+
+    let mut list = path.as_os_str().as_bytes().to_vec();
+    list.push(0);
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from=-")
+        .write_stdin(list)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("1 2 8 "))
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn files0_from_non_utf8_source() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = tempfile::tempdir()?;
+    let source = dir.path().join(OsStr::from_bytes(b"caf\xe9.lst"));
+    fs::write(&source, format!("{}\0", FOX))?;
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from")
+        .arg(&source)
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("tests/expected/fox.txt.out")?)
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from_empty_name() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/files0_bad.txt.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", "tests/inputs/files0_bad.txt"])
+        .assert()
+        .failure()
+        .stdout(expected)
+        .stderr("tests/inputs/files0_bad.txt:2: invalid zero-length file name\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_files0_from_with_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", "tests/inputs/files0.txt", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_files0_from() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("cannot open '{}' for reading: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", &bad])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn format_json() -> TestResult {
+    run(
+        &["--format", "json", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.json.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn format_csv() -> TestResult {
+    run(
+        &["--format=csv", "-lwmcL", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.lwmcL.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn format_json_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "--total", "always"])
+        .write_stdin("one two\n")
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"path":"-","bytes":8,"lines":1,"words":2,"error":null}"#,
+            "\n",
+            r#"{"path":null,"bytes":8,"lines":1,"words":2,"error":null}"#,
+            "\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn format_csv_total_only() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format=csv", "--total=only", "-l", FOX, MULTIBYTE])
+        .assert()
+        .success()
+        .stdout("path,lines,error\n,4,\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn format_json_error() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!(
+        r#"{{"path":"{}","bytes":null,"lines":null,"words":null,"error":"No such file"#,
+        bad
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", &bad])
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(expected));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn words_unicode() -> TestResult {
+    run(
+        &["--word-rule", "unicode", WORDS],
+        "tests/expected/words.txt.unicode.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn words_regex() -> TestResult {
+    run(
+        &["-w", "--word-regex", "[[:alpha:]]+", WORDS],
+        "tests/expected/words.txt.regex.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_word_regex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--word-regex", "(", WORDS])
+        .assert()
+        .failure()
+        .stderr("Invalid --word-regex \"(\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn progress_keeps_output() -> TestResult {
+    run(
+        &["--progress", FOX, MULTIBYTE],
+        "tests/expected/fox_multibyte.total_always.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn progress_running_counts() -> TestResult {
+    use assert_cmd::cargo::CommandCargoExt;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        process::Stdio,
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    let mut child = std::process::Command::cargo_bin(PRG)?
+        .arg("--progress")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (tx, rx) = mpsc::channel();
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    thread::spawn(move || {
+        for line in stderr.lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+
+    // The counts of the first line show while the input is still open.
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"a b\n")?;
+    stdin.flush()?;
+    let partial = rx.recv_timeout(Duration::from_secs(10))?;
+    assert_eq!(partial, "      1       2       4");
+
+    stdin.write_all(b"c\n")?;
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "      2       3       6\n"
+    );
+    Ok(())
+}